@@ -1,6 +1,24 @@
-extends MultiMeshInstance3D
+class_name DepthMesh
+extends MeshInstance3D
 
-Vector3[] points = []
+func _ready():
+	var material := StandardMaterial3D.new()
+	material.shading_mode = BaseMaterial3D.SHADING_MODE_UNSHADED
+	material.vertex_color_use_as_albedo = true
+	material_override = material
 
-func _ready() -> void:
-	self.add
+# Triangles come clockwise seen from the camera, Godot's front faces
+func set_triangles(vertices: PackedVector3Array, colors: PackedColorArray, indices: PackedInt32Array):
+	if indices.is_empty():
+		mesh = null
+		return
+	
+	var arrays = []
+	arrays.resize(Mesh.ARRAY_MAX)
+	arrays[Mesh.ARRAY_VERTEX] = vertices
+	arrays[Mesh.ARRAY_COLOR] = colors
+	arrays[Mesh.ARRAY_INDEX] = indices
+	
+	var array_mesh := ArrayMesh.new()
+	array_mesh.add_surface_from_arrays(Mesh.PRIMITIVE_TRIANGLES, arrays)
+	mesh = array_mesh
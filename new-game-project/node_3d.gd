@@ -1,13 +1,16 @@
 extends Node3D
 
 const num_points: int = 50
-const scale_pos: float = -0.001
 const scale_color: float = 1 / num_points
 
 @onready var right_hand: XRController3D = get_node("XROrigin3D/RightHand")
 
 @onready var world_cloud: Pointcloud = get_node("World_Pointcloud")
 @onready var hand_cloud: Pointcloud = get_node("XROrigin3D/RightHand/Hand_Pointcloud")
+@onready var hand_mesh: DepthMesh = get_node("XROrigin3D/RightHand/Hand_Mesh")
+
+# Show the camera as a triangle mesh instead of points, A/X switches
+@export var meshed: bool = true
 
 var stream: Stream
 
@@ -30,8 +33,6 @@ func _ready():
 		get_viewport().use_xr = true
 		xr_interface.environment_blend_mode = XRInterface.XR_ENV_BLEND_MODE_ALPHA_BLEND
 		get_viewport().transparent_bg = true
-		hand_cloud.global_scale(Vector3(scale_pos, scale_pos, scale_pos))
-		#world_cloud.global_scale(Vector3(scale_pos, scale_pos, scale_pos))
 	else:
 		print("OpenXR not initialized, please check if your headset is connected")
 		
@@ -44,24 +45,47 @@ func _ready():
 	hand_cloud.set_points(points, colors)
 	
 	stream = Stream.new()
+	stream.meshed = meshed
+	show_mode()
 	stream.start("10.42.0.1", 1234)
 
 
+# Only the form the stream is sending is shown
+func show_mode():
+	hand_cloud.visible = !meshed
+	hand_mesh.visible = meshed
+
+
 var last_pressed = false
+var last_switch_pressed = false
 
 func _process(delta):
 	if(stream.new_points()):
 		hand_cloud.set_points(stream.current_points, stream.current_colors)
+	if(stream.new_mesh()):
+		hand_mesh.set_triangles(stream.current_mesh_vertices, stream.current_mesh_colors, stream.current_mesh_indices)
+	
+	var switch_pressed = right_hand.is_button_pressed("ax_button")
+	if(switch_pressed && !last_switch_pressed):
+		meshed = !meshed
+		stream.meshed = meshed
+		show_mode()
+	last_switch_pressed = switch_pressed
 	
 	var pressed = right_hand.get_float("trigger") > 0.
 	if(pressed && !last_pressed):
 		var points_clone: Array[Vector3] = []
+		var colors_clone: Array[Color] = []
 		print(right_hand.global_position)
 		
-		for point in stream.current_points:
-			points_clone.append(right_hand.to_global(point * scale_pos))
+		# Mesh vertices are the points while meshing
+		var source_points = stream.current_mesh_vertices if meshed else stream.current_points
+		var source_colors = stream.current_mesh_colors if meshed else stream.current_colors
+		for i in len(source_points):
+			points_clone.append(right_hand.to_global(source_points[i]))
+			colors_clone.append(source_colors[i])
 		
-		world_cloud.add_points(points_clone, stream.current_colors)
+		world_cloud.add_points(points_clone, colors_clone)
 		last_pressed = true
 		print("points! ", len(points_clone))
 	elif (!pressed): last_pressed = false
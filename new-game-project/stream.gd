@@ -59,32 +59,57 @@ func process() -> bool:
 var current_packet_length: int = -1
 var current_index: int = 0
 
+# Ask for triangle meshes instead of points
+var meshed: bool = false
+
 var current_points: Array[Vector3] = []
 var current_colors: Array[Color] = []
+
+var current_mesh_vertices: PackedVector3Array = []
+var current_mesh_colors: PackedColorArray = []
+var current_mesh_indices: PackedInt32Array = []
+var has_new_mesh = false
+var current_frame_id: int = -1
+var current_stamp_msec: int = 0
 var has_new_points = false
 
+# Camera connection state reported with the last error, see ConnectionState
+const CAMERA_STATES = ["connecting", "streaming", "stalled", "disconnected"]
+var camera_state: int = -1
+
 var overflow_bytes: Array = []
 
 
 func parse_bytes():
-	_stream.put_u8(2);
+	if meshed:
+		_stream.put_u8(7)
+	else:
+		_stream.put_u8(2)
 	
 	var type = _stream.get_32()
 	
 	match type:
+		0:
+			var state = _stream.get_32()
+			if state != camera_state:
+				camera_state = state
+				print("No points, camera is %s" % CAMERA_STATES[state] if state < len(CAMERA_STATES) else str(state))
 		1:
+			var frame_id = _stream.get_u64()
+			var stamp_msec = _stream.get_u64()
 			var len = _stream.get_32()
 			
-			print("Got type %d with len %d" % [type, len])
+			print("Got frame %d at %.3fs with len %d" % [frame_id, stamp_msec / 1000., len])
 			
 			var points: Array[Vector3] = []
 			var colors: Array[Color] = []
 			#
+			# Metres, already in Godot's axes
 			for i in len:
 				points.append(Vector3(
-					_stream.get_32(),
-					_stream.get_32(),
-					_stream.get_32()
+					_stream.get_float(),
+					_stream.get_float(),
+					_stream.get_float()
 				))
 				#
 				colors.append(Color(
@@ -95,7 +120,48 @@ func parse_bytes():
 			
 			current_points = points
 			current_colors = colors
+			current_frame_id = frame_id
+			current_stamp_msec = stamp_msec
 			has_new_points = true
+		8:
+			var frame_id = _stream.get_u64()
+			var stamp_msec = _stream.get_u64()
+			var vertex_count = _stream.get_32()
+			var index_count = _stream.get_32()
+			
+			print("Got mesh %d at %.3fs with %d triangles" % [frame_id, stamp_msec / 1000., index_count / 3])
+			
+			var vertices: PackedVector3Array = []
+			var colors: PackedColorArray = []
+			var indices: PackedInt32Array = []
+			vertices.resize(vertex_count)
+			colors.resize(vertex_count)
+			indices.resize(index_count)
+			
+			for i in vertex_count:
+				vertices[i] = Vector3(
+					_stream.get_float(),
+					_stream.get_float(),
+					_stream.get_float()
+				)
+				colors[i] = Color(
+					_stream.get_u8() / 256.,
+					_stream.get_u8() / 256.,
+					_stream.get_u8() / 256.
+				)
+			
+			for i in index_count:
+				indices[i] = _stream.get_u32()
+			
+			current_mesh_vertices = vertices
+			current_mesh_colors = colors
+			current_mesh_indices = indices
+			current_frame_id = frame_id
+			current_stamp_msec = stamp_msec
+			has_new_mesh = true
+		4:
+			print("Server shutting down")
+			_stream.disconnect_from_host()
 
 
 func new_points() -> bool:
@@ -103,3 +169,10 @@ func new_points() -> bool:
 		has_new_points = false
 		return true
 	return false
+
+
+func new_mesh() -> bool:
+	if(has_new_mesh):
+		has_new_mesh = false
+		return true
+	return false
@@ -0,0 +1,611 @@
+//! Finding checkerboard corners in IR and RGB images
+//!
+//! Inner corners of a checkerboard are saddle points of the image intensity,
+//! so they show up as strongly negative Hessian determinants. The strongest
+//! saddles are linked into a grid by walking from one to the next, and the
+//! grid is numbered from the corner next to the board's dark corner square.
+
+use std::collections::{HashMap, VecDeque};
+
+use ndarray::{Array2, Array3};
+
+/// Blur applied before taking second derivatives, in pixels
+const SADDLE_SIGMA: f32 = 1.5;
+
+/// Fraction of the strongest saddle response a corner candidate needs
+///
+/// Corners where the outer squares meet the margin score about a quarter of
+/// an inner corner, image noise far less.
+const MIN_RESPONSE: f32 = 0.15;
+
+/// How far a corner may be from where the grid predicts it, relative to the
+/// distance between corners
+const SNAP_TOLERANCE: f64 = 0.35;
+
+/// Iterations of the subpixel refinement
+const REFINE_ITERATIONS: usize = 10;
+
+/// Checkerboard calibration target
+///
+/// `columns` and `rows` count inner corners, where four squares meet. Their
+/// sum must be odd so the board looks different when turned upside down, and
+/// the square at the corner next to the first inner corner is dark.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Board {
+    pub columns: usize,
+    pub rows: usize,
+    pub square_mm: f64,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self {
+            columns: 7,
+            rows: 6,
+            square_mm: 35.,
+        }
+    }
+}
+
+impl Board {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.columns < 3 || self.rows < 3 {
+            return Err("A checkerboard needs at least 3x3 inner corners".into());
+        }
+
+        if (self.columns + self.rows).is_multiple_of(2) {
+            return Err(format!(
+                "A {}x{} checkerboard looks the same upside down, use one with an odd number of inner corners in total",
+                self.columns, self.rows
+            )
+            .into());
+        }
+
+        if self.square_mm.is_nan() || self.square_mm <= 0. {
+            return Err(format!("Square size must be positive, got {} mm", self.square_mm).into());
+        }
+
+        Ok(())
+    }
+
+    pub fn corner_count(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    /// Inner corners on the board plane in millimetres, row by row
+    pub fn object_points(&self) -> Vec<(f64, f64)> {
+        (0..self.rows)
+            .flat_map(|row| (0..self.columns).map(move |column| (column, row)))
+            .map(|(column, row)| (column as f64 * self.square_mm, row as f64 * self.square_mm))
+            .collect()
+    }
+}
+
+/// Scales an IR image to 0..1, saturating the brightest percent
+pub fn gray_from_ir(ir: &Array2<u16>) -> Array2<f32> {
+    let mut values: Vec<u16> = ir.iter().copied().collect();
+    let index = (values.len() * 99 / 100).min(values.len().saturating_sub(1));
+    let bright = if values.is_empty() {
+        1.
+    } else {
+        f32::from(*values.select_nth_unstable(index).1).max(1.)
+    };
+
+    ir.mapv(|value| (f32::from(value) / bright).min(1.))
+}
+
+/// Luma of an RGB image scaled to 0..1
+pub fn gray_from_rgb(rgb: &Array3<u8>) -> Array2<f32> {
+    let (height, width, _) = rgb.dim();
+    Array2::from_shape_fn((height, width), |(y, x)| {
+        (0.299 * f32::from(rgb[(y, x, 0)])
+            + 0.587 * f32::from(rgb[(y, x, 1)])
+            + 0.114 * f32::from(rgb[(y, x, 2)]))
+            / 255.
+    })
+}
+
+/// Finds the board's inner corners, row by row, in pixel coordinates
+pub fn find_corners(image: &Array2<f32>, board: &Board) -> Option<Vec<(f64, f64)>> {
+    let blurred = gaussian_blur(image, SADDLE_SIGMA);
+    let (candidates, strength): (Vec<_>, Vec<_>) =
+        saddle_points(&blurred, 3 * board.corner_count())
+            .into_iter()
+            .unzip();
+    if candidates.len() < board.corner_count() {
+        return None;
+    }
+
+    // The strongest saddles are the likeliest to be inner corners
+    let grid = (0..candidates.len()).take(10).find_map(|seed| {
+        let grid = grow_grid(&candidates, seed, board)?;
+        strongest_window(&grid, &strength, board)
+    })?;
+    let grid = grid
+        .into_iter()
+        .map(|(key, index)| (key, candidates[index]))
+        .collect();
+    let corners = orient(&grid, &blurred, board)?;
+
+    let spacing = (0..board.corner_count() - 1)
+        .filter(|i| (i + 1) % board.columns != 0)
+        .map(|i| distance2(corners[i], corners[i + 1]).sqrt())
+        .fold(f64::INFINITY, f64::min);
+    let radius = (0.4 * spacing).clamp(2., 10.) as usize;
+
+    Some(
+        corners
+            .into_iter()
+            .map(|corner| refine(&blurred, corner, radius))
+            .collect(),
+    )
+}
+
+/// Local maxima of the saddle response and their strength, strongest first
+fn saddle_points(image: &Array2<f32>, limit: usize) -> Vec<((f64, f64), f32)> {
+    let (height, width) = image.dim();
+    let mut response = Array2::<f32>::zeros((height, width));
+
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width.saturating_sub(1) {
+            let dxx = image[(y, x + 1)] - 2. * image[(y, x)] + image[(y, x - 1)];
+            let dyy = image[(y + 1, x)] - 2. * image[(y, x)] + image[(y - 1, x)];
+            let dxy = (image[(y + 1, x + 1)] - image[(y - 1, x + 1)] - image[(y + 1, x - 1)]
+                + image[(y - 1, x - 1)])
+                / 4.;
+            response[(y, x)] = dxy * dxy - dxx * dyy;
+        }
+    }
+
+    let strongest = response.iter().copied().fold(0., f32::max);
+    if strongest <= 0. {
+        return Vec::new();
+    }
+
+    let radius = 3;
+    let mut peaks = Vec::new();
+    for y in radius..height.saturating_sub(radius) {
+        for x in radius..width.saturating_sub(radius) {
+            let value = response[(y, x)];
+            if value < MIN_RESPONSE * strongest {
+                continue;
+            }
+
+            let is_peak = (y - radius..=y + radius).all(|ny| {
+                (x - radius..=x + radius).all(|nx| {
+                    let other = response[(ny, nx)];
+                    // Break ties towards the first pixel so plateaus give one peak
+                    other < value || (other == value && (ny, nx) >= (y, x))
+                })
+            });
+            if is_peak {
+                peaks.push((value, (x as f64, y as f64)));
+            }
+        }
+    }
+
+    peaks.sort_by(|a, b| b.0.total_cmp(&a.0));
+    peaks.truncate(limit);
+    peaks
+        .into_iter()
+        .map(|(value, point)| (point, value))
+        .collect()
+}
+
+/// Links candidates into a grid starting from `seed`
+///
+/// Returns the index of the candidate at each (column, row), in either
+/// orientation. The grid can run past the board onto the weaker corners
+/// where its outer squares meet the margin.
+fn grow_grid(
+    candidates: &[(f64, f64)],
+    seed: usize,
+    board: &Board,
+) -> Option<HashMap<(i32, i32), usize>> {
+    let origin = candidates[seed];
+
+    // The nearest candidate gives one axis, the nearest across it the other
+    let mut nearest: Vec<usize> = (0..candidates.len()).filter(|&i| i != seed).collect();
+    nearest.sort_by(|&a, &b| {
+        distance2(candidates[a], origin).total_cmp(&distance2(candidates[b], origin))
+    });
+
+    let first = sub(candidates[*nearest.first()?], origin);
+    let second = nearest
+        .iter()
+        .take(8)
+        .skip(1)
+        .map(|&i| sub(candidates[i], origin))
+        .find(|v| {
+            let cos = dot(*v, first) / (length(*v) * length(first));
+            cos.abs() < 0.5 && length(*v) < 2. * length(first) && length(first) < 2. * length(*v)
+        })?;
+    let axes = [first, second];
+
+    let mut grid: HashMap<(i32, i32), (f64, f64)> = HashMap::from([((0, 0), origin)]);
+    let mut indices = HashMap::from([((0, 0), seed)]);
+    let mut used = vec![false; candidates.len()];
+    used[seed] = true;
+
+    let mut queue = VecDeque::from([(0, 0)]);
+    while let Some((i, j)) = queue.pop_front() {
+        let here = grid[&(i, j)];
+
+        for (axis, sign) in [(0, 1), (0, -1), (1, 1), (1, -1)] {
+            let step_index = if axis == 0 { (sign, 0) } else { (0, sign) };
+            let target = (i + step_index.0, j + step_index.1);
+            if grid.contains_key(&target) {
+                continue;
+            }
+
+            // Continue the line through the previous corner where there is
+            // one, so the spacing follows the perspective
+            let behind = (i - step_index.0, j - step_index.1);
+            let step = match grid.get(&behind) {
+                Some(&previous) => sub(here, previous),
+                None => local_step(&grid, (i, j), step_index)
+                    .unwrap_or_else(|| scale(axes[axis], f64::from(sign))),
+            };
+
+            let predicted = add(here, step);
+            let tolerance = SNAP_TOLERANCE * length(step);
+
+            let found = candidates
+                .iter()
+                .enumerate()
+                .filter(|&(k, &c)| !used[k] && distance2(c, predicted) < tolerance * tolerance)
+                .min_by(|a, b| distance2(*a.1, predicted).total_cmp(&distance2(*b.1, predicted)));
+
+            if let Some((k, &corner)) = found {
+                used[k] = true;
+                grid.insert(target, corner);
+                indices.insert(target, k);
+                queue.push_back(target);
+            }
+        }
+
+        // Far more than a board's worth is not a board
+        if grid.len() > 4 * board.corner_count() {
+            return None;
+        }
+    }
+
+    (grid.len() >= board.corner_count()).then_some(indices)
+}
+
+/// The fully found board-sized window of the grid with the strongest corners
+///
+/// Returns its candidates with indices shifted to start at 0.
+fn strongest_window(
+    grid: &HashMap<(i32, i32), usize>,
+    strength: &[f32],
+    board: &Board,
+) -> Option<HashMap<(i32, i32), usize>> {
+    let (min_i, max_i) = grid.keys().fold((i32::MAX, i32::MIN), |(lo, hi), k| {
+        (lo.min(k.0), hi.max(k.0))
+    });
+    let (min_j, max_j) = grid.keys().fold((i32::MAX, i32::MIN), |(lo, hi), k| {
+        (lo.min(k.1), hi.max(k.1))
+    });
+    let (columns, rows) = (board.columns as i32, board.rows as i32);
+
+    // Every placement of the board, either way round, with its top left
+    let windows = [(columns, rows), (rows, columns)]
+        .into_iter()
+        .flat_map(|size| {
+            (min_i..=max_i - size.0 + 1)
+                .flat_map(move |i0| (min_j..=max_j - size.1 + 1).map(move |j0| ((i0, j0), size)))
+        });
+
+    let ((i0, j0), size) = windows
+        .filter_map(|((i0, j0), size)| {
+            let total = (i0..i0 + size.0)
+                .flat_map(|i| (j0..j0 + size.1).map(move |j| (i, j)))
+                .map(|key| grid.get(&key).map(|&index| strength[index]))
+                .sum::<Option<f32>>()?;
+            Some((total, ((i0, j0), size)))
+        })
+        .max_by(|a, b| a.0.total_cmp(&b.0))?
+        .1;
+
+    Some(
+        grid.iter()
+            .filter(|&(&(i, j), _)| {
+                (i0..i0 + size.0).contains(&i) && (j0..j0 + size.1).contains(&j)
+            })
+            .map(|(&(i, j), &index)| ((i - i0, j - j0), index))
+            .collect(),
+    )
+}
+
+/// Step to `(i, j) + step_index` copied from a neighbouring line of the grid
+fn local_step(
+    grid: &HashMap<(i32, i32), (f64, f64)>,
+    (i, j): (i32, i32),
+    step_index: (i32, i32),
+) -> Option<(f64, f64)> {
+    // Neighbours across the direction of travel
+    let across = [(step_index.1, step_index.0), (-step_index.1, -step_index.0)];
+
+    across.iter().find_map(|&(di, dj)| {
+        let from = grid.get(&(i + di, j + dj))?;
+        let to = grid.get(&(i + di + step_index.0, j + dj + step_index.1))?;
+        Some(sub(*to, *from))
+    })
+}
+
+/// Numbers the grid by the board's convention, row by row
+fn orient(
+    grid: &HashMap<(i32, i32), (f64, f64)>,
+    image: &Array2<f32>,
+    board: &Board,
+) -> Option<Vec<(f64, f64)>> {
+    let (columns, rows) = (board.columns as i32, board.rows as i32);
+    let swapped = !grid.contains_key(&(columns - 1, rows - 1));
+
+    // Index into the grid for board corner (column, row), four ways round
+    let lookup = |column: i32, row: i32, flip_columns: bool, flip_rows: bool| {
+        let column = if flip_columns {
+            columns - 1 - column
+        } else {
+            column
+        };
+        let row = if flip_rows { rows - 1 - row } else { row };
+        let key = if swapped {
+            (row, column)
+        } else {
+            (column, row)
+        };
+        grid[&key]
+    };
+
+    let orientations = [(false, false), (true, false), (false, true), (true, true)];
+    orientations
+        .into_iter()
+        .find_map(|(flip_columns, flip_rows)| {
+            let at = |column, row| lookup(column, row, flip_columns, flip_rows);
+
+            // Columns must run left to right of rows as seen from the front
+            let along = sub(at(1, 0), at(0, 0));
+            let down = sub(at(0, 1), at(0, 0));
+            if along.0 * down.1 - along.1 * down.0 <= 0. {
+                return None;
+            }
+
+            // The corner square is dark, the square beside it light
+            let corner_square = sub(at(0, 0), scale(add(along, down), 0.5));
+            let beside = add(at(0, 0), scale(sub(along, down), 0.5));
+            if sample(image, corner_square)? >= sample(image, beside)? {
+                return None;
+            }
+
+            Some(
+                (0..rows)
+                    .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                    .map(|(column, row)| at(column, row))
+                    .collect(),
+            )
+        })
+}
+
+/// Moves a corner to where the image gradients around it meet
+///
+/// Every gradient near a corner is perpendicular to the line from the corner
+/// to where it is taken, which gives a least squares system for the corner.
+fn refine(image: &Array2<f32>, corner: (f64, f64), radius: usize) -> (f64, f64) {
+    let (height, width) = image.dim();
+    let mut current = corner;
+    let sigma = radius as f64 / 2.;
+
+    for _ in 0..REFINE_ITERATIONS {
+        let (cx, cy) = (current.0.round() as isize, current.1.round() as isize);
+        let r = radius as isize;
+        if cx - r < 1 || cy - r < 1 || cx + r >= width as isize - 1 || cy + r >= height as isize - 1
+        {
+            return current;
+        }
+
+        let (mut a, mut b, mut c) = (0., 0., 0.);
+        let (mut bx, mut by) = (0., 0.);
+        for y in cy - r..=cy + r {
+            for x in cx - r..=cx + r {
+                let (ux, uy) = (x as usize, y as usize);
+                let gx = f64::from(image[(uy, ux + 1)] - image[(uy, ux - 1)]) / 2.;
+                let gy = f64::from(image[(uy + 1, ux)] - image[(uy - 1, ux)]) / 2.;
+                let weight =
+                    (-distance2((x as f64, y as f64), current) / (2. * sigma * sigma)).exp();
+
+                let (gxx, gxy, gyy) = (gx * gx * weight, gx * gy * weight, gy * gy * weight);
+                a += gxx;
+                b += gxy;
+                c += gyy;
+                bx += gxx * x as f64 + gxy * y as f64;
+                by += gxy * x as f64 + gyy * y as f64;
+            }
+        }
+
+        let determinant = a * c - b * b;
+        if determinant.abs() < 1e-12 {
+            return current;
+        }
+
+        let next = (
+            (c * bx - b * by) / determinant,
+            (a * by - b * bx) / determinant,
+        );
+        if distance2(next, corner) > (radius * radius) as f64 {
+            // Wandered off, keep the last good estimate
+            return current;
+        }
+
+        let moved = distance2(next, current);
+        current = next;
+        if moved < 1e-6 {
+            break;
+        }
+    }
+
+    current
+}
+
+/// Separable Gaussian blur, clamping at the borders
+fn gaussian_blur(image: &Array2<f32>, sigma: f32) -> Array2<f32> {
+    let radius = (3. * sigma).ceil() as isize;
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2. * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = kernel.iter().sum();
+    let kernel: Vec<f32> = kernel.iter().map(|k| k / total).collect();
+
+    let (height, width) = image.dim();
+    let clamp = |value: isize, size: usize| value.clamp(0, size as isize - 1) as usize;
+
+    let horizontal = Array2::from_shape_fn((height, width), |(y, x)| -> f32 {
+        kernel
+            .iter()
+            .enumerate()
+            .map(|(k, weight)| weight * image[(y, clamp(x as isize + k as isize - radius, width))])
+            .sum()
+    });
+
+    Array2::from_shape_fn((height, width), |(y, x)| -> f32 {
+        kernel
+            .iter()
+            .enumerate()
+            .map(|(k, weight)| {
+                weight * horizontal[(clamp(y as isize + k as isize - radius, height), x)]
+            })
+            .sum()
+    })
+}
+
+/// Bilinearly interpolated value at (x, y), None outside the image
+fn sample(image: &Array2<f32>, (x, y): (f64, f64)) -> Option<f32> {
+    let (height, width) = image.dim();
+    if !(x >= 0. && y >= 0. && x < (width - 1) as f64 && y < (height - 1) as f64) {
+        return None;
+    }
+
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (fx, fy) = ((x - x0 as f64) as f32, (y - y0 as f64) as f32);
+
+    let top = image[(y0, x0)] * (1. - fx) + image[(y0, x0 + 1)] * fx;
+    let bottom = image[(y0 + 1, x0)] * (1. - fx) + image[(y0 + 1, x0 + 1)] * fx;
+    Some(top * (1. - fy) + bottom * fy)
+}
+
+fn add(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    (a.0 + b.0, a.1 + b.1)
+}
+
+fn sub(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    (a.0 - b.0, a.1 - b.1)
+}
+
+fn scale(a: (f64, f64), factor: f64) -> (f64, f64) {
+    (a.0 * factor, a.1 * factor)
+}
+
+fn dot(a: (f64, f64), b: (f64, f64)) -> f64 {
+    a.0 * b.0 + a.1 * b.1
+}
+
+fn length(a: (f64, f64)) -> f64 {
+    dot(a, a).sqrt()
+}
+
+fn distance2(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let d = sub(a, b);
+    dot(d, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array1;
+
+    use super::*;
+    use crate::calibrate::linalg::{mat3_vec, solve, Mat3};
+
+    /// Board plane in millimetres to pixels, tilted and a little in
+    /// perspective
+    const BOARD_TO_IMAGE: Mat3 = [[1.1, 0.15, 70.], [-0.1, 1.05, 45.], [0.0004, 0.0002, 1.]];
+
+    fn project(h: &Mat3, (x, y): (f64, f64)) -> (f64, f64) {
+        let [u, v, w] = mat3_vec(h, &[x, y, 1.]);
+        (u / w, v / w)
+    }
+
+    /// Draws the board with 4x4 samples per pixel in front of a grey wall
+    fn render(board: &Board) -> Array2<f32> {
+        let h = Array2::from_shape_fn((3, 3), |(i, j)| BOARD_TO_IMAGE[i][j]);
+        let columns: Vec<Array1<f64>> = (0..3)
+            .map(|j| {
+                let unit = Array1::from_shape_fn(3, |i| if i == j { 1. } else { 0. });
+                solve(&h, &unit).unwrap()
+            })
+            .collect();
+        let inverse = [0, 1, 2].map(|i| [0, 1, 2].map(|j| columns[j][i]));
+
+        let square = board.square_mm;
+        let shade = |(x, y): (f64, f64)| -> f32 {
+            let inside = |value: f64, corners: usize| {
+                (-1.5 * square..(corners as f64 + 0.5) * square).contains(&value)
+            };
+            if !inside(x, board.columns) || !inside(y, board.rows) {
+                return 0.5;
+            }
+
+            let square_x = (x / square).floor() as i64 + 1;
+            let square_y = (y / square).floor() as i64 + 1;
+            let on_squares = (0..=board.columns as i64).contains(&square_x)
+                && (0..=board.rows as i64).contains(&square_y);
+            if on_squares && (square_x + square_y) % 2 == 0 {
+                0.08
+            } else {
+                1.
+            }
+        };
+
+        Array2::from_shape_fn((240, 320), |(y, x)| {
+            let samples = (0..4).flat_map(|i| (0..4).map(move |j| (i, j)));
+            samples
+                .map(|(i, j)| {
+                    let pixel = (
+                        x as f64 + (f64::from(j) - 1.5) / 4.,
+                        y as f64 + (f64::from(i) - 1.5) / 4.,
+                    );
+                    shade(project(&inverse, pixel))
+                })
+                .sum::<f32>()
+                / 16.
+        })
+    }
+
+    #[test]
+    fn finds_corners_of_a_rendered_board() {
+        let board = Board {
+            square_mm: 20.,
+            ..Board::default()
+        };
+
+        let corners = find_corners(&render(&board), &board).unwrap();
+
+        assert_eq!(corners.len(), board.corner_count());
+        for (corner, object) in corners.iter().zip(board.object_points()) {
+            let expected = project(&BOARD_TO_IMAGE, object);
+            assert!(
+                distance2(*corner, expected) < 0.1 * 0.1,
+                "corner at {:?}, expected {:?}",
+                corner,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn no_corners_without_a_board() {
+        let image = Array2::from_elem((240, 320), 0.5);
+
+        assert_eq!(find_corners(&image, &Board::default()), None);
+    }
+}
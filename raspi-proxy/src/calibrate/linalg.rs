@@ -0,0 +1,272 @@
+//! The little dense linear algebra calibration needs
+
+use ndarray::{Array1, Array2};
+
+pub type Vec3 = [f64; 3];
+pub type Mat3 = [[f64; 3]; 3];
+
+pub const IDENTITY: Mat3 = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+
+pub fn mat3_mul(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut result = [[0.; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+pub fn mat3_transpose(a: &Mat3) -> Mat3 {
+    let mut result = [[0.; 3]; 3];
+    for (i, row) in a.iter().enumerate() {
+        for (j, value) in row.iter().enumerate() {
+            result[j][i] = *value;
+        }
+    }
+    result
+}
+
+pub fn mat3_vec(a: &Mat3, v: &Vec3) -> Vec3 {
+    [
+        a[0][0] * v[0] + a[0][1] * v[1] + a[0][2] * v[2],
+        a[1][0] * v[0] + a[1][1] * v[1] + a[1][2] * v[2],
+        a[2][0] * v[0] + a[2][1] * v[1] + a[2][2] * v[2],
+    ]
+}
+
+pub fn cross(a: &Vec3, b: &Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn norm(v: &Vec3) -> f64 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+/// Rotation matrix of a rotation vector, axis times angle in radians
+pub fn rodrigues(r: &Vec3) -> Mat3 {
+    let angle = norm(r);
+    if angle < 1e-12 {
+        return IDENTITY;
+    }
+
+    let [x, y, z] = [r[0] / angle, r[1] / angle, r[2] / angle];
+    let (sin, cos) = angle.sin_cos();
+    let c = 1. - cos;
+
+    [
+        [cos + x * x * c, x * y * c - z * sin, x * z * c + y * sin],
+        [y * x * c + z * sin, cos + y * y * c, y * z * c - x * sin],
+        [z * x * c - y * sin, z * y * c + x * sin, cos + z * z * c],
+    ]
+}
+
+/// Rotation vector of a rotation matrix, the inverse of [`rodrigues`]
+pub fn rodrigues_inverse(rotation: &Mat3) -> Vec3 {
+    let trace = rotation[0][0] + rotation[1][1] + rotation[2][2];
+    let cos = ((trace - 1.) / 2.).clamp(-1., 1.);
+    let angle = cos.acos();
+
+    if angle < 1e-12 {
+        return [0.; 3];
+    }
+
+    if angle > std::f64::consts::PI - 1e-6 {
+        // sin is ~0, take the axis from the diagonal instead
+        let axis = [
+            ((rotation[0][0] + 1.) / 2.).max(0.).sqrt(),
+            ((rotation[1][1] + 1.) / 2.).max(0.).sqrt(),
+            ((rotation[2][2] + 1.) / 2.).max(0.).sqrt(),
+        ];
+        let axis = [
+            axis[0],
+            axis[1].copysign(rotation[0][1] + rotation[1][0]),
+            axis[2].copysign(rotation[0][2] + rotation[2][0]),
+        ];
+        return [axis[0] * angle, axis[1] * angle, axis[2] * angle];
+    }
+
+    let scale = angle / (2. * angle.sin());
+    [
+        (rotation[2][1] - rotation[1][2]) * scale,
+        (rotation[0][2] - rotation[2][0]) * scale,
+        (rotation[1][0] - rotation[0][1]) * scale,
+    ]
+}
+
+/// Eigenvalues and eigenvectors of a symmetric matrix by Jacobi rotations
+///
+/// Eigenvalues come out in ascending order, the eigenvectors are the
+/// matching columns.
+pub fn symmetric_eigen(matrix: &Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+    let n = matrix.nrows();
+    let mut a = matrix.clone();
+    let mut vectors = Array2::eye(n);
+
+    for _ in 0..100 {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[(i, j)] * a[(i, j)])
+            .sum();
+        if off_diagonal < 1e-30 {
+            break;
+        }
+
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[(p, q)].abs() < 1e-300 {
+                    continue;
+                }
+
+                let theta = (a[(q, q)] - a[(p, p)]) / (2. * a[(p, q)]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.).sqrt());
+                let c = 1. / (t * t + 1.).sqrt();
+                let s = t * c;
+
+                for k in 0..n {
+                    let (akp, akq) = (a[(k, p)], a[(k, q)]);
+                    a[(k, p)] = c * akp - s * akq;
+                    a[(k, q)] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[(p, k)], a[(q, k)]);
+                    a[(p, k)] = c * apk - s * aqk;
+                    a[(q, k)] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (vectors[(k, p)], vectors[(k, q)]);
+                    vectors[(k, p)] = c * vkp - s * vkq;
+                    vectors[(k, q)] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| a[(i, i)].total_cmp(&a[(j, j)]));
+
+    let values = Array1::from_shape_fn(n, |i| a[(order[i], order[i])]);
+    let sorted = Array2::from_shape_fn((n, n), |(i, j)| vectors[(i, order[j])]);
+    (values, sorted)
+}
+
+/// Unit vector x minimising |A x|, for homogeneous least squares
+pub fn null_vector(a: &Array2<f64>) -> Array1<f64> {
+    let (_, vectors) = symmetric_eigen(&a.t().dot(a));
+    vectors.column(0).to_owned()
+}
+
+/// Solves A x = b by Gaussian elimination with partial pivoting
+pub fn solve(a: &Array2<f64>, b: &Array1<f64>) -> Option<Array1<f64>> {
+    let n = a.nrows();
+    let mut a = a.clone();
+    let mut b = b.clone();
+
+    for column in 0..n {
+        let pivot =
+            (column..n).max_by(|&i, &j| a[(i, column)].abs().total_cmp(&a[(j, column)].abs()))?;
+        if a[(pivot, column)].abs() < 1e-300 {
+            return None;
+        }
+
+        if pivot != column {
+            for k in 0..n {
+                a.swap((pivot, k), (column, k));
+            }
+            b.swap(pivot, column);
+        }
+
+        for row in column + 1..n {
+            let factor = a[(row, column)] / a[(column, column)];
+            if factor == 0. {
+                continue;
+            }
+            for k in column..n {
+                a[(row, k)] -= factor * a[(column, k)];
+            }
+            b[row] -= factor * b[column];
+        }
+    }
+
+    let mut x = Array1::zeros(n);
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[(row, k)] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[(row, row)];
+    }
+
+    Some(x)
+}
+
+/// Rotation closest to `m` in the Frobenius norm, `m (mᵀm)^-1/2`
+pub fn nearest_rotation(m: &Mat3) -> Mat3 {
+    let mtm = mat3_mul(&mat3_transpose(m), m);
+    let (values, vectors) = symmetric_eigen(&Array2::from_shape_fn((3, 3), |(i, j)| mtm[i][j]));
+
+    let mut inverse_sqrt = [[0.; 3]; 3];
+    for (i, row) in inverse_sqrt.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3)
+                .map(|k| vectors[(i, k)] * vectors[(j, k)] / values[k].max(1e-300).sqrt())
+                .sum();
+        }
+    }
+
+    mat3_mul(m, &inverse_sqrt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Mat3, b: &Mat3) {
+        for (row_a, row_b) in a.iter().zip(b) {
+            for (x, y) in row_a.iter().zip(row_b) {
+                assert!((x - y).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn rodrigues_round_trips() {
+        for r in [[0.; 3], [0.1, -0.2, 0.3], [0., 3.1, 0.], [-1.2, 0.4, 0.9]] {
+            let rotation = rodrigues(&r);
+
+            assert_close(&mat3_mul(&rotation, &mat3_transpose(&rotation)), &IDENTITY);
+            let back = rodrigues_inverse(&rotation);
+            assert!((0..3).all(|i| (back[i] - r[i]).abs() < 1e-9), "{:?} -> {:?}", r, back);
+        }
+    }
+
+    #[test]
+    fn solves_linear_systems() {
+        let a = Array2::from_shape_vec((3, 3), vec![0., 2., 1., 1., 1., 0., 3., 0., 1.]).unwrap();
+        let x = Array1::from(vec![1., -2., 0.5]);
+
+        let found = solve(&a, &a.dot(&x)).unwrap();
+
+        assert!(found.iter().zip(&x).all(|(a, b)| (a - b).abs() < 1e-12), "{}", found);
+        assert_eq!(solve(&Array2::zeros((2, 2)), &Array1::zeros(2)), None);
+    }
+
+    #[test]
+    fn null_vector_of_rank_deficient_matrix() {
+        let a = Array2::from_shape_vec((3, 3), vec![1., 2., 3., 2., 4., 6., 1., 0., -1.]).unwrap();
+
+        let v = null_vector(&a);
+
+        assert!(a.dot(&v).iter().all(|value| value.abs() < 1e-9), "{}", v);
+        assert!((v.dot(&v) - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn nearest_rotation_of_a_noisy_rotation() {
+        let rotation = rodrigues(&[0.3, -0.1, 0.2]);
+        let noisy = rotation.map(|row| row.map(|value| value * 1.02));
+
+        assert_close(&nearest_rotation(&noisy), &rotation);
+    }
+}
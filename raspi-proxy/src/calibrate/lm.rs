@@ -0,0 +1,97 @@
+use ndarray::{Array1, Array2};
+
+use crate::calibrate::linalg::solve;
+
+/// Most iterations before the refinement gives up improving
+const MAX_ITERATIONS: usize = 100;
+
+/// Minimises the sum of squared residuals by Levenberg-Marquardt
+///
+/// The Jacobian is taken by forward differences, which is plenty for the
+/// hundred or so parameters of a calibration. Returns the RMS of the final
+/// residuals.
+pub fn levenberg_marquardt(params: &mut [f64], residuals: impl Fn(&[f64]) -> Vec<f64>) -> f64 {
+    let n = params.len();
+    let mut current = Array1::from(residuals(params));
+    let mut cost = current.dot(&current);
+    let mut lambda = 1e-3;
+
+    for _ in 0..MAX_ITERATIONS {
+        // Forward difference Jacobian, one column per parameter
+        let mut jacobian = Array2::zeros((current.len(), n));
+        let mut shifted = params.to_vec();
+        for j in 0..n {
+            let step = 1e-6 * params[j].abs().max(1.);
+            shifted[j] = params[j] + step;
+            let column = Array1::from(residuals(&shifted));
+            shifted[j] = params[j];
+
+            jacobian
+                .column_mut(j)
+                .assign(&((&column - &current) / step));
+        }
+
+        let jtj = jacobian.t().dot(&jacobian);
+        let gradient = jacobian.t().dot(&current);
+
+        // Raise lambda until a step lowers the cost
+        let mut improved = false;
+        while lambda < 1e12 {
+            let mut damped = jtj.clone();
+            for i in 0..n {
+                damped[(i, i)] += lambda * jtj[(i, i)].max(1e-12);
+            }
+
+            let Some(delta) = solve(&damped, &-&gradient) else {
+                lambda *= 10.;
+                continue;
+            };
+
+            let candidate: Vec<f64> = params.iter().zip(&delta).map(|(p, d)| p + d).collect();
+            let candidate_residuals = Array1::from(residuals(&candidate));
+            let candidate_cost = candidate_residuals.dot(&candidate_residuals);
+
+            if candidate_cost < cost {
+                let converged = (cost - candidate_cost) < 1e-12 * cost;
+
+                params.copy_from_slice(&candidate);
+                current = candidate_residuals;
+                cost = candidate_cost;
+                lambda = (lambda / 10.).max(1e-12);
+                improved = !converged;
+                break;
+            }
+
+            lambda *= 10.;
+        }
+
+        if !improved {
+            break;
+        }
+    }
+
+    (cost / current.len().max(1) as f64).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_an_exponential() {
+        let xs: Vec<f64> = (0..20).map(|i| f64::from(i) * 0.25).collect();
+        let ys: Vec<f64> = xs.iter().map(|x| 2.5 * (-0.7 * x).exp()).collect();
+
+        let mut params = [1., 0.];
+        let rms = levenberg_marquardt(&mut params, |p| {
+            xs.iter()
+                .zip(&ys)
+                .map(|(x, y)| p[0] * (p[1] * x).exp() - y)
+                .collect()
+        });
+
+        assert!(rms < 1e-6, "rms {}", rms);
+        assert!((params[0] - 2.5).abs() < 1e-5, "{:?}", params);
+        assert!((params[1] + 0.7).abs() < 1e-5, "{:?}", params);
+    }
+}
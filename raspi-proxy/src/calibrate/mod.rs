@@ -0,0 +1,689 @@
+//! Calibration subcommands
+//!
+//! The cameras are shown a checkerboard from different angles, its corners
+//! are found in every view, and camera models are fitted to them by least
+//! squares starting from Zhang's closed-form solution.
+
+mod checkerboard;
+mod linalg;
+mod lm;
+mod zhang;
+
+use std::path::{Path, PathBuf};
+
+pub use checkerboard::Board;
+
+use crate::{
+    calibrate::{
+        checkerboard::{find_corners, gray_from_ir, gray_from_rgb},
+        linalg::{
+            mat3_mul, mat3_transpose, mat3_vec, nearest_rotation, rodrigues, rodrigues_inverse,
+        },
+        linalg::{Mat3, Vec3},
+        lm::levenberg_marquardt,
+        zhang::{homography, intrinsics_from_homographies, pose_from_homography},
+    },
+    camera::{
+        decode_frame, point_to_pixel, Calibration, CameraCalibration, CameraIntrinsics, DeviceInfo,
+        Extrinsics, FrameConfig, FrameSource, RayTable, DEFAULT_CALIBRATION_PATH,
+        DEFAULT_INTRINSICS, DEFAULT_INTRINSICS_SHAPE,
+    },
+    cli::CalibrateArgs,
+};
+
+/// Frames to look through for each view asked for before giving up
+const MAX_FRAMES_PER_VIEW: usize = 50;
+
+/// How far the board must move between views, as the mean distance between
+/// its IR corners in pixels
+const MIN_VIEW_CHANGE: f64 = 8.;
+
+/// Reprojection error in pixels above which a calibration is suspect
+const MAX_GOOD_RMS: f64 = 1.;
+
+/// Residual of a corner that lands behind the camera
+const BEHIND_CAMERA: f64 = 1e4;
+
+/// How far towards each image corner, as a fraction of the way from the
+/// middle in x and in y, the board must be seen before k3 is fitted
+///
+/// k3 only matters near the corners, elsewhere it trades off against k1 and
+/// k2 and bends the lens out of shape where there were no corners to fit.
+const K3_REACH: f64 = 0.7;
+
+/// Fraction of the depth image a new lens may leave without rays beyond
+/// what `DEFAULT_INTRINSICS` leaves, before it is refused
+const MAX_EXTRA_MISSING: f64 = 0.01;
+
+/// Board corners found in one frame, row by row
+struct View {
+    ir: Vec<(f64, f64)>,
+    rgb: Option<Vec<(f64, f64)>>,
+}
+
+/// Views of the board and the image sizes they were found in
+struct Capture {
+    views: Vec<View>,
+    /// (height, width) of the IR image
+    ir_shape: (usize, usize),
+    /// (height, width) of the RGB image, when RGB corners were wanted
+    rgb_shape: Option<(usize, usize)>,
+}
+
+/// Rigid transform, e.g. from board to camera coordinates
+#[derive(Debug, Clone, Copy)]
+struct Pose {
+    rotation: Mat3,
+    translation: Vec3,
+}
+
+impl Pose {
+    fn transform(&self, point: &Vec3) -> Vec3 {
+        let rotated = mat3_vec(&self.rotation, point);
+        [0, 1, 2].map(|i| rotated[i] + self.translation[i])
+    }
+
+    /// This transform followed by `next`
+    fn then(&self, next: &Pose) -> Pose {
+        Pose {
+            rotation: mat3_mul(&next.rotation, &self.rotation),
+            translation: next.transform(&self.translation),
+        }
+    }
+
+    fn inverse(&self) -> Pose {
+        let rotation = mat3_transpose(&self.rotation);
+        let translation = mat3_vec(&rotation, &self.translation).map(|value| -value);
+        Pose {
+            rotation,
+            translation,
+        }
+    }
+
+    /// Rotation vector followed by translation
+    fn to_params(self) -> [f64; 6] {
+        let [rx, ry, rz] = rodrigues_inverse(&self.rotation);
+        let [tx, ty, tz] = self.translation;
+        [rx, ry, rz, tx, ty, tz]
+    }
+
+    fn from_params(params: &[f64]) -> Pose {
+        Pose {
+            rotation: rodrigues(&[params[0], params[1], params[2]]),
+            translation: [params[3], params[4], params[5]],
+        }
+    }
+}
+
+/// Finds the RGB camera's intrinsics and its pose relative to the depth
+/// camera, and writes them to the calibration file
+///
+/// The depth camera's intrinsics are taken as known, from the calibration
+/// file if it has them, else from the camera's factory values.
+pub fn run_rgb(args: CalibrateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let Target {
+        mut source,
+        device,
+        output,
+    } = open(&args)?;
+    let mut calibration = load_existing(&output)?;
+
+    let capture = capture(source.as_mut(), &args.board, args.views, true)?;
+    let rgb_shape = capture.rgb_shape.ok_or("No RGB images to calibrate")?;
+    let object = args.board.object_points();
+
+    let depth = depth_intrinsics(&calibration, device.as_ref(), capture.ir_shape);
+    let rgb_views: Vec<&[(f64, f64)]> = capture
+        .views
+        .iter()
+        .filter_map(|view| view.rgb.as_deref())
+        .collect();
+    let fit_k3 = reaches_corners(&rgb_views, rgb_shape);
+    let (rgb, rgb_poses, rgb_alone_rms) = calibrate_camera(&args.board, &rgb_views, fit_k3)?;
+    debug!("RGB intrinsics alone fit to {:.3} px", rgb_alone_rms);
+
+    let depth_poses = capture
+        .views
+        .iter()
+        .map(|view| fit_pose(&depth, &object, &view.ir))
+        .collect::<Option<Vec<Pose>>>()
+        .ok_or("Cannot find the board's pose in an IR view")?;
+
+    // Start from the mean of the depth to RGB transforms the views imply
+    let (rotation_sum, translation_sum) = depth_poses.iter().zip(&rgb_poses).fold(
+        ([[0.; 3]; 3], [0.; 3]),
+        |(rotation, translation), (depth_pose, rgb_pose)| {
+            let view = depth_pose.inverse().then(rgb_pose);
+            (
+                [0, 1, 2].map(|i| [0, 1, 2].map(|j| rotation[i][j] + view.rotation[i][j])),
+                [0, 1, 2].map(|i| translation[i] + view.translation[i]),
+            )
+        },
+    );
+    let extrinsics = Pose {
+        rotation: nearest_rotation(&rotation_sum),
+        translation: translation_sum.map(|value| value / depth_poses.len() as f64),
+    };
+
+    // Refine everything together: RGB intrinsics, extrinsics, then the board
+    // pose in depth camera coordinates for every view
+    let mut params = intrinsics_params(&rgb, fit_k3);
+    let lens = params.len();
+    params.extend(extrinsics.to_params());
+    for pose in &depth_poses {
+        params.extend(pose.to_params());
+    }
+
+    let residuals = |params: &[f64]| -> Vec<f64> {
+        let rgb = intrinsics_from_params(&params[..lens]);
+        let extrinsics = Pose::from_params(&params[lens..lens + 6]);
+
+        capture
+            .views
+            .iter()
+            .zip(params[lens + 6..].chunks(6))
+            .flat_map(|(view, pose)| {
+                let pose = Pose::from_params(pose);
+                let mut residuals = reprojection(&depth, &pose, &object, &view.ir);
+                if let Some(ref corners) = view.rgb {
+                    residuals.extend(reprojection(
+                        &rgb,
+                        &pose.then(&extrinsics),
+                        &object,
+                        corners,
+                    ));
+                }
+                residuals
+            })
+            .collect()
+    };
+
+    levenberg_marquardt(&mut params, residuals);
+
+    // Each view's residuals are its IR corners then its RGB corners
+    let final_residuals = residuals(&params);
+    let per_view = 2 * object.len();
+    let (ir_residuals, rgb_residuals): (Vec<_>, Vec<_>) = final_residuals
+        .chunks(per_view)
+        .enumerate()
+        .partition(|(i, _)| i % 2 == 0);
+    let ir_rms = rms_pixels(ir_residuals.into_iter().flat_map(|(_, r)| r));
+    let rgb_rms = rms_pixels(rgb_residuals.into_iter().flat_map(|(_, r)| r));
+
+    let rgb = intrinsics_from_params(&params[..lens]);
+    let extrinsics = Pose::from_params(&params[lens..lens + 6]);
+
+    info!(
+        "RGB intrinsics at {}x{}: {:?}",
+        rgb_shape.1, rgb_shape.0, rgb
+    );
+    info!("Extrinsics: {:?}", extrinsics);
+    info!(
+        "Reprojection error: {:.3} px in IR, {:.3} px in RGB",
+        ir_rms, rgb_rms
+    );
+    if ir_rms.max(rgb_rms) > MAX_GOOD_RMS {
+        warn!("Reprojection error is high, check the board is flat and the depth camera is calibrated");
+    }
+
+    calibration.rgb = Some(CameraCalibration {
+        intrinsics: rgb,
+        shape: rgb_shape,
+        rms: Some(rgb_rms),
+    });
+    calibration.extrinsics = Some(Extrinsics {
+        rotation: extrinsics.rotation,
+        translation: extrinsics.translation,
+    });
+    calibration.save(&output)?;
+
+    info!("Wrote {}", output.display());
+    Ok(())
+}
+
+/// Finds the depth camera's intrinsics and writes them to the calibration
+/// file
+///
+/// Depth and IR come through the same lens and sensor, so the board's
+/// corners in the IR image calibrate the depth camera.
+pub fn run_tof(args: CalibrateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let Target { mut source, output, .. } = open(&args)?;
+    let mut calibration = load_existing(&output)?;
+
+    let depth = calibrate_tof(source.as_mut(), &args.board, args.views, args.force)?;
+
+    if calibration.extrinsics.is_some() {
+        warn!(
+            "{} has an RGB calibration made with the old depth intrinsics, run calibrate-rgb again",
+            output.display()
+        );
+    }
+
+    calibration.depth = Some(depth);
+    calibration.save(&output)?;
+
+    info!("Wrote {}", output.display());
+    Ok(())
+}
+
+/// Depth camera calibration from `views` views of the board
+///
+/// k3 is only fitted when the board reached the image corners, and the lens
+/// goes through `check_coverage`.
+fn calibrate_tof(
+    source: &mut dyn FrameSource,
+    board: &Board,
+    views: usize,
+    force: bool,
+) -> Result<CameraCalibration, Box<dyn std::error::Error>> {
+    let capture = capture(source, board, views, false)?;
+    let ir_views: Vec<&[(f64, f64)]> = capture
+        .views
+        .iter()
+        .map(|view| view.ir.as_slice())
+        .collect();
+    let fit_k3 = reaches_corners(&ir_views, capture.ir_shape);
+    let (depth, _, rms) = calibrate_camera(board, &ir_views, fit_k3)?;
+
+    let (height, width) = capture.ir_shape;
+    info!("Depth intrinsics at {}x{}: {:?}", width, height, depth);
+    info!("Reprojection error: {:.3} px", rms);
+    if rms > MAX_GOOD_RMS {
+        warn!("Reprojection error is high, check the board is flat and evenly lit");
+    }
+
+    check_coverage(&depth, capture.ir_shape, force)?;
+
+    Ok(CameraCalibration {
+        intrinsics: depth,
+        shape: capture.ir_shape,
+        rms: Some(rms),
+    })
+}
+
+/// Fails if a depth lens leaves many more pixels without rays than the
+/// default one does, unless `force` is set
+///
+/// Distortion is only pinned down where the board was seen, past that it
+/// can fold over and leave whole image corners without rays.
+fn check_coverage(
+    depth: &CameraIntrinsics,
+    (height, width): (usize, usize),
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let missing = RayTable::new(depth, (height, width)).missing();
+    let default = DEFAULT_INTRINSICS.scaled(DEFAULT_INTRINSICS_SHAPE, (height, width));
+    let default_missing = RayTable::new(&default, (height, width)).missing();
+    let allowed = default_missing + (MAX_EXTRA_MISSING * (width * height) as f64) as usize;
+
+    if missing > allowed {
+        let message = format!(
+            "The lens leaves {} of {} pixels without rays, against {} for the default lens",
+            missing,
+            width * height,
+            default_missing
+        );
+        if !force {
+            return Err(format!(
+                "{}. Add views with the board near the image corners, or pass --force to save it anyway",
+                message
+            )
+            .into());
+        }
+        warn!("{}, saving it anyway", message);
+    } else if missing > default_missing {
+        warn!("Add views with the board near the image corners to cover the whole lens");
+    }
+
+    Ok(())
+}
+
+/// Camera being calibrated and the file its calibration goes to
+struct Target {
+    source: Box<dyn FrameSource>,
+    device: Option<DeviceInfo>,
+    output: PathBuf,
+}
+
+/// Opens the source and picks the calibration file to update, the camera's
+/// own unless one was given
+fn open(args: &CalibrateArgs) -> Result<Target, Box<dyn std::error::Error>> {
+    let mut source = args.source.open()?;
+
+    let device = source.device_info().unwrap_or_else(|e| {
+        warn!("Error reading device info: {}", e);
+        None
+    });
+    match device {
+        Some(ref device) => info!("Calibrating camera {}", device.serial),
+        None => info!("Camera has no serial number"),
+    }
+
+    let output = match (&args.output, &device) {
+        (Some(output), _) => output.clone(),
+        (None, Some(device)) => Calibration::device_path(&device.serial),
+        (None, None) => PathBuf::from(DEFAULT_CALIBRATION_PATH),
+    };
+
+    Ok(Target {
+        source,
+        device,
+        output,
+    })
+}
+
+/// The calibration file as it is, so only the calibrated parts change
+fn load_existing(path: &Path) -> Result<Calibration, Box<dyn std::error::Error>> {
+    if path.exists() {
+        Calibration::load(path)
+    } else {
+        Ok(Calibration::default())
+    }
+}
+
+/// Depth camera intrinsics at the IR image size
+fn depth_intrinsics(
+    calibration: &Calibration,
+    device: Option<&DeviceInfo>,
+    shape: (usize, usize),
+) -> CameraIntrinsics {
+    match (calibration.depth, device.and_then(|device| device.lens)) {
+        (Some(depth), _) => depth.intrinsics.scaled(depth.shape, shape),
+        (None, Some(lens)) => lens.scaled(DEFAULT_INTRINSICS_SHAPE, shape),
+        (None, None) => DEFAULT_INTRINSICS.scaled(DEFAULT_INTRINSICS_SHAPE, shape),
+    }
+}
+
+/// Collects `count` views of the board that differ from each other
+///
+/// Frames where the board is not fully visible, in both cameras when
+/// `with_rgb` is set, are skipped.
+fn capture(
+    source: &mut dyn FrameSource,
+    board: &Board,
+    count: usize,
+    with_rgb: bool,
+) -> Result<Capture, Box<dyn std::error::Error>> {
+    source.apply_config(&FrameConfig::default())?;
+
+    let mut views: Vec<View> = Vec::new();
+    let mut shapes = None;
+
+    info!(
+        "Looking for a {}x{} checkerboard, move it around in view",
+        board.columns, board.rows
+    );
+
+    for attempt in 1..=count * MAX_FRAMES_PER_VIEW {
+        if views.len() == count {
+            break;
+        }
+
+        let frames = match source.fetch_frame().and_then(|raw| decode_frame(&raw)) {
+            Ok(frames) => frames,
+            Err(e) => {
+                warn!("Skipping frame: {}", e);
+                continue;
+            }
+        };
+
+        let ir = frames.ir.as_ref().ok_or("Frames have no IR image")?;
+        let Some(ir_corners) = find_corners(&gray_from_ir(ir), board) else {
+            debug!("No board in the IR image of frame {}", frames.frame_id);
+            continue;
+        };
+
+        let rgb_corners = match (with_rgb, &frames.rgb) {
+            (false, _) => None,
+            (true, None) => return Err("Frames have no RGB image".into()),
+            (true, Some(rgb)) => match find_corners(&gray_from_rgb(rgb), board) {
+                Some(corners) => Some(corners),
+                None => {
+                    debug!("No board in the RGB image of frame {}", frames.frame_id);
+                    continue;
+                }
+            },
+        };
+
+        let frame_shapes = (
+            ir.dim(),
+            frames.rgb.as_ref().map(|rgb| (rgb.dim().0, rgb.dim().1)),
+        );
+        if *shapes.get_or_insert(frame_shapes) != frame_shapes {
+            return Err("Image sizes changed while capturing".into());
+        }
+
+        let moved = views
+            .iter()
+            .all(|view| mean_distance(&view.ir, &ir_corners) >= MIN_VIEW_CHANGE);
+        if !moved {
+            trace!("Frame {} is too close to an earlier view", frames.frame_id);
+            continue;
+        }
+
+        views.push(View {
+            ir: ir_corners,
+            rgb: rgb_corners,
+        });
+        info!(
+            "View {}/{} from frame {} after {} frames",
+            views.len(),
+            count,
+            frames.frame_id,
+            attempt
+        );
+    }
+
+    match shapes {
+        Some((ir_shape, rgb_shape)) if views.len() == count => Ok(Capture {
+            views,
+            ir_shape,
+            rgb_shape: rgb_shape.filter(|_| with_rgb),
+        }),
+        _ => Err(format!("Only found {} of {} views of the board", views.len(), count).into()),
+    }
+}
+
+/// Fits a camera's intrinsics and the board's pose in each view
+///
+/// k3 stays 0 unless `fit_k3` is set. Also returns the RMS reprojection
+/// error in pixels.
+fn calibrate_camera(
+    board: &Board,
+    views: &[&[(f64, f64)]],
+    fit_k3: bool,
+) -> Result<(CameraIntrinsics, Vec<Pose>, f64), Box<dyn std::error::Error>> {
+    let object = board.object_points();
+
+    let homographies = views
+        .iter()
+        .map(|image| homography(&object, image))
+        .collect::<Option<Vec<Mat3>>>()
+        .ok_or("A view of the board is degenerate")?;
+    let pinhole = intrinsics_from_homographies(&homographies)
+        .ok_or("Cannot solve the intrinsics, tilt the board more between views")?;
+    let (fx, fy, u0, v0) = pinhole;
+
+    let undistorted = CameraIntrinsics::new(fx, fy, u0, v0, 0., 0., 0., 0., 0.);
+    let mut params = intrinsics_params(&undistorted, fit_k3);
+    let lens = params.len();
+    for h in &homographies {
+        let (rotation, translation) = pose_from_homography(h, pinhole);
+        params.extend(
+            Pose {
+                rotation,
+                translation,
+            }
+            .to_params(),
+        );
+    }
+
+    let rms = levenberg_marquardt(&mut params, |params| {
+        let intrinsics = intrinsics_from_params(&params[..lens]);
+        views
+            .iter()
+            .zip(params[lens..].chunks(6))
+            .flat_map(|(image, pose)| {
+                reprojection(&intrinsics, &Pose::from_params(pose), &object, image)
+            })
+            .collect()
+    });
+
+    // The RMS is per coordinate, two make up each corner's distance
+    let poses = params[lens..].chunks(6).map(Pose::from_params).collect();
+    Ok((
+        intrinsics_from_params(&params[..lens]),
+        poses,
+        rms * 2f64.sqrt(),
+    ))
+}
+
+/// Board pose in one view with the intrinsics known
+fn fit_pose(
+    intrinsics: &CameraIntrinsics,
+    object: &[(f64, f64)],
+    image: &[(f64, f64)],
+) -> Option<Pose> {
+    let h = homography(object, image)?;
+    let (rotation, translation) = pose_from_homography(
+        &h,
+        (intrinsics.fx, intrinsics.fy, intrinsics.u0, intrinsics.v0),
+    );
+
+    let mut params = Pose {
+        rotation,
+        translation,
+    }
+    .to_params();
+    levenberg_marquardt(&mut params, |params| {
+        reprojection(intrinsics, &Pose::from_params(params), object, image)
+    });
+
+    Some(Pose::from_params(&params))
+}
+
+/// Where board corners project to minus where they were seen, x and y for
+/// each corner
+fn reprojection(
+    intrinsics: &CameraIntrinsics,
+    pose: &Pose,
+    object: &[(f64, f64)],
+    image: &[(f64, f64)],
+) -> Vec<f64> {
+    object
+        .iter()
+        .zip(image)
+        .flat_map(|(&(x, y), &(u, v))| {
+            let [px, py, pz] = pose.transform(&[x, y, 0.]);
+            match point_to_pixel((px, py, pz), intrinsics) {
+                Some((pu, pv)) => [pu - u, pv - v],
+                None => [BEHIND_CAMERA; 2],
+            }
+        })
+        .collect()
+}
+
+/// RMS distance in pixels from x and y residual pairs
+fn rms_pixels<'a>(residuals: impl Iterator<Item = &'a f64>) -> f64 {
+    let (sum, count) = residuals.fold((0., 0), |(sum, count), r| (sum + r * r, count + 1));
+    (sum / (count / 2).max(1) as f64).sqrt()
+}
+
+fn mean_distance(a: &[(f64, f64)], b: &[(f64, f64)]) -> f64 {
+    let total: f64 = a
+        .iter()
+        .zip(b)
+        .map(|(p, q)| ((p.0 - q.0).powi(2) + (p.1 - q.1).powi(2)).sqrt())
+        .sum();
+    total / a.len().max(1) as f64
+}
+
+/// Whether corners were seen towards all four corners of the image, see
+/// `K3_REACH`
+fn reaches_corners(views: &[&[(f64, f64)]], (height, width): (usize, usize)) -> bool {
+    let (half_width, half_height) = (width as f64 / 2., height as f64 / 2.);
+    let reached = |x_sign: f64, y_sign: f64| {
+        views.iter().flat_map(|view| view.iter()).any(|&(u, v)| {
+            let x = (u - half_width) / half_width * x_sign;
+            let y = (v - half_height) / half_height * y_sign;
+            x >= K3_REACH && y >= K3_REACH
+        })
+    };
+
+    [(-1., -1.), (1., -1.), (-1., 1.), (1., 1.)]
+        .into_iter()
+        .all(|(x_sign, y_sign)| reached(x_sign, y_sign))
+}
+
+/// Parameters being fitted, k3 last and only when `fit_k3` is set
+fn intrinsics_params(i: &CameraIntrinsics, fit_k3: bool) -> Vec<f64> {
+    let mut params = vec![i.fx, i.fy, i.u0, i.v0, i.k1, i.k2, i.p1, i.p2];
+    if fit_k3 {
+        params.push(i.k3);
+    }
+    params
+}
+
+/// Inverse of `intrinsics_params`, with k3 0 when it was not fitted
+fn intrinsics_from_params(p: &[f64]) -> CameraIntrinsics {
+    let k3 = p.get(8).copied().unwrap_or(0.);
+    CameraIntrinsics::new(p[0], p[1], p[2], p[3], p[4], p[5], k3, p[6], p[7])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{SyntheticScene, SyntheticSource};
+
+    #[test]
+    fn calibrates_the_synthetic_depth_camera() {
+        let board = Board::default();
+        let mut source = SyntheticSource::new(SyntheticScene::Checkerboard {
+            columns: board.columns,
+            rows: board.rows,
+            square_mm: board.square_mm,
+        });
+
+        // The synthetic camera reports the lens it renders with,
+        // SYNTHETIC_DEPTH_INTRINSICS, as its factory lens
+        let truth = source.device_info().unwrap().unwrap().lens.unwrap();
+
+        let depth = calibrate_tof(&mut source, &board, 15, false).unwrap();
+
+        let found = depth.intrinsics;
+        assert_eq!(depth.shape, DEFAULT_INTRINSICS_SHAPE);
+        assert!(depth.rms.unwrap() < MAX_GOOD_RMS, "{:?}", depth);
+        assert!((found.fx - truth.fx).abs() < 2., "{:?}", found);
+        assert!((found.fy - truth.fy).abs() < 2., "{:?}", found);
+        assert!((found.u0 - truth.u0).abs() < 2., "{:?}", found);
+        assert!((found.v0 - truth.v0).abs() < 2., "{:?}", found);
+        // k2 takes up part of k1 with k3 held at 0
+        assert!((found.k1 - truth.k1).abs() < 0.05, "{:?}", found);
+        assert_eq!(found.k3, 0., "the board never reaches the image corners");
+    }
+
+    #[test]
+    fn k3_needs_every_image_corner() {
+        let shape = (240, 320);
+        let middle = [(100., 80.), (220., 160.)];
+        let corners = [(10., 10.), (310., 12.), (5., 230.), (315., 235.)];
+
+        assert!(!reaches_corners(&[&middle], shape));
+        assert!(!reaches_corners(&[&middle, &corners[..3]], shape));
+        assert!(reaches_corners(&[&middle, &corners[..2], &corners[2..]], shape));
+    }
+
+    #[test]
+    fn overfit_lens_is_refused_unless_forced() {
+        // k2 and k3 fitted to views that stayed away from the corners
+        let overfit = CameraIntrinsics {
+            k2: 0.471,
+            k3: -1.987,
+            ..DEFAULT_INTRINSICS
+        };
+
+        assert!(check_coverage(&DEFAULT_INTRINSICS, DEFAULT_INTRINSICS_SHAPE, false).is_ok());
+        assert!(check_coverage(&overfit, DEFAULT_INTRINSICS_SHAPE, false).is_err());
+        assert!(check_coverage(&overfit, DEFAULT_INTRINSICS_SHAPE, true).is_ok());
+    }
+}
@@ -0,0 +1,231 @@
+//! Closed-form starting points for calibration, after Zhang (2000)
+//!
+//! A planar target seen by a pinhole camera is related to its image by a
+//! homography. Each homography constrains the intrinsics, and once those are
+//! known it also gives the target's pose. Distortion is ignored here and
+//! left to the refinement.
+
+use ndarray::Array2;
+
+use crate::calibrate::linalg::{cross, mat3_mul, nearest_rotation, norm, null_vector, Mat3, Vec3};
+
+/// Homography from target plane (x, y) to image (u, v) by normalised DLT
+pub fn homography(object: &[(f64, f64)], image: &[(f64, f64)]) -> Option<Mat3> {
+    if object.len() < 4 || object.len() != image.len() {
+        return None;
+    }
+
+    let object_norm = normalisation(object);
+    let image_norm = normalisation(image);
+
+    let mut a = Array2::zeros((2 * object.len(), 9));
+    for (i, (&o, &p)) in object.iter().zip(image).enumerate() {
+        let (x, y) = apply(&object_norm, o);
+        let (u, v) = apply(&image_norm, p);
+
+        let rows = [
+            [-x, -y, -1., 0., 0., 0., u * x, u * y, u],
+            [0., 0., 0., -x, -y, -1., v * x, v * y, v],
+        ];
+        for (r, row) in rows.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                a[(2 * i + r, j)] = *value;
+            }
+        }
+    }
+
+    let h = null_vector(&a);
+    let h = [[h[0], h[1], h[2]], [h[3], h[4], h[5]], [h[6], h[7], h[8]]];
+
+    // Undo the normalisation, H = T_image^-1 * H_norm * T_object
+    let image_inverse = [
+        [
+            1. / image_norm[0][0],
+            0.,
+            -image_norm[0][2] / image_norm[0][0],
+        ],
+        [
+            0.,
+            1. / image_norm[1][1],
+            -image_norm[1][2] / image_norm[1][1],
+        ],
+        [0., 0., 1.],
+    ];
+    let h = mat3_mul(&mat3_mul(&image_inverse, &h), &object_norm);
+
+    if h[2][2].abs() < 1e-300 {
+        return None;
+    }
+    Some(h.map(|row| row.map(|value| value / h[2][2])))
+}
+
+/// Focal lengths and principal point from three or more homographies
+///
+/// Assumes zero skew. Returns (fx, fy, u0, v0).
+pub fn intrinsics_from_homographies(homographies: &[Mat3]) -> Option<(f64, f64, f64, f64)> {
+    if homographies.len() < 3 {
+        return None;
+    }
+
+    // v_ij from the paper, the constraint h_i^T B h_j on B's six entries
+    let v = |h: &Mat3, i: usize, j: usize| -> [f64; 6] {
+        let (hi, hj) = ([h[0][i], h[1][i], h[2][i]], [h[0][j], h[1][j], h[2][j]]);
+        [
+            hi[0] * hj[0],
+            hi[0] * hj[1] + hi[1] * hj[0],
+            hi[1] * hj[1],
+            hi[2] * hj[0] + hi[0] * hj[2],
+            hi[2] * hj[1] + hi[1] * hj[2],
+            hi[2] * hj[2],
+        ]
+    };
+
+    // One extra row pins the skew to zero
+    let mut a = Array2::zeros((2 * homographies.len() + 1, 6));
+    for (k, h) in homographies.iter().enumerate() {
+        let v12 = v(h, 0, 1);
+        let v11 = v(h, 0, 0);
+        let v22 = v(h, 1, 1);
+        for j in 0..6 {
+            a[(2 * k, j)] = v12[j];
+            a[(2 * k + 1, j)] = v11[j] - v22[j];
+        }
+    }
+    a[(2 * homographies.len(), 1)] = 1.;
+
+    let b = null_vector(&a);
+    let (b11, b12, b22, b13, b23, b33) = (b[0], b[1], b[2], b[3], b[4], b[5]);
+
+    let denominator = b11 * b22 - b12 * b12;
+    if denominator.abs() < 1e-300 || b11.abs() < 1e-300 {
+        return None;
+    }
+
+    let v0 = (b12 * b13 - b11 * b23) / denominator;
+    let lambda = b33 - (b13 * b13 + v0 * (b12 * b13 - b11 * b23)) / b11;
+    let fx2 = lambda / b11;
+    let fy2 = lambda * b11 / denominator;
+    if !(fx2 > 0. && fy2 > 0.) {
+        return None;
+    }
+
+    let fx = fx2.sqrt();
+    let fy = fy2.sqrt();
+    let u0 = -b13 * fx2 / lambda;
+
+    Some((fx, fy, u0, v0))
+}
+
+/// Pose of the target plane from its homography and the intrinsics
+///
+/// Returns the rotation and translation taking target to camera coordinates.
+pub fn pose_from_homography(h: &Mat3, (fx, fy, u0, v0): (f64, f64, f64, f64)) -> (Mat3, Vec3) {
+    // K^-1 h for each column of the homography
+    let unproject = |c: usize| -> Vec3 {
+        let y = (h[1][c] - v0 * h[2][c]) / fy;
+        let x = (h[0][c] - u0 * h[2][c]) / fx;
+        [x, y, h[2][c]]
+    };
+
+    let (c0, c1, c2) = (unproject(0), unproject(1), unproject(2));
+    let mut scale = 2. / (norm(&c0) + norm(&c1));
+
+    // The target must be in front of the camera
+    if c2[2] * scale < 0. {
+        scale = -scale;
+    }
+
+    let r0 = c0.map(|value| value * scale);
+    let r1 = c1.map(|value| value * scale);
+    let r2 = cross(&r0, &r1);
+    let translation = c2.map(|value| value * scale);
+
+    let rotation = nearest_rotation(&[
+        [r0[0], r1[0], r2[0]],
+        [r0[1], r1[1], r2[1]],
+        [r0[2], r1[2], r2[2]],
+    ]);
+
+    (rotation, translation)
+}
+
+/// Similarity moving the points' centroid to 0 with mean distance sqrt(2)
+fn normalisation(points: &[(f64, f64)]) -> Mat3 {
+    let n = points.len() as f64;
+    let (cx, cy) = points
+        .iter()
+        .fold((0., 0.), |(x, y), p| (x + p.0 / n, y + p.1 / n));
+    let mean_distance = points
+        .iter()
+        .map(|p| ((p.0 - cx).powi(2) + (p.1 - cy).powi(2)).sqrt())
+        .sum::<f64>()
+        / n;
+    let s = std::f64::consts::SQRT_2 / mean_distance.max(1e-12);
+
+    [[s, 0., -s * cx], [0., s, -s * cy], [0., 0., 1.]]
+}
+
+fn apply(t: &Mat3, (x, y): (f64, f64)) -> (f64, f64) {
+    (t[0][0] * x + t[0][2], t[1][1] * y + t[1][2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibrate::linalg::{mat3_transpose, mat3_vec, rodrigues};
+
+    fn project(h: &Mat3, (x, y): (f64, f64)) -> (f64, f64) {
+        let [u, v, w] = mat3_vec(h, &[x, y, 1.]);
+        (u / w, v / w)
+    }
+
+    fn grid() -> Vec<(f64, f64)> {
+        (0..5)
+            .flat_map(|row| (0..4).map(move |column| (column as f64 * 35., row as f64 * 35.)))
+            .collect()
+    }
+
+    #[test]
+    fn homography_recovered_from_known_points() {
+        let truth = [[1.2, 0.1, 40.], [-0.05, 0.9, 25.], [0.0004, -0.0002, 1.]];
+        let object = grid();
+        let image: Vec<_> = object.iter().map(|&point| project(&truth, point)).collect();
+
+        let h = homography(&object, &image).unwrap();
+
+        for (row, truth_row) in h.iter().zip(&truth) {
+            for (value, truth) in row.iter().zip(truth_row) {
+                assert!((value - truth).abs() < 1e-8, "{:?}", h);
+            }
+        }
+        assert_eq!(homography(&object[..3], &image[..3]), None);
+    }
+
+    #[test]
+    fn intrinsics_recovered_from_tilted_views() {
+        let (fx, fy, u0, v0) = (230., 232., 165., 118.);
+        let object = grid();
+
+        let homographies: Vec<Mat3> = [[0.3, 0., 0.], [0., 0.35, 0.1], [-0.25, 0.2, -0.1]]
+            .iter()
+            .map(|tilt| {
+                let r = rodrigues(tilt);
+                let t = [-50., -70., 600.];
+                // K [r1 r2 t], the plane z = 0 seen by the camera
+                let columns = [[r[0][0], r[1][0], r[2][0]], [r[0][1], r[1][1], r[2][1]], t];
+                let h = columns.map(|[x, y, z]| [fx * x + u0 * z, fy * y + v0 * z, z]);
+                let h = mat3_transpose(&h);
+                let image: Vec<_> = object.iter().map(|&point| project(&h, point)).collect();
+                homography(&object, &image).unwrap()
+            })
+            .collect();
+
+        let (fx_found, fy_found, u0_found, v0_found) =
+            intrinsics_from_homographies(&homographies).unwrap();
+
+        assert!((fx_found - fx).abs() < 1e-4, "fx {}", fx_found);
+        assert!((fy_found - fy).abs() < 1e-4, "fy {}", fy_found);
+        assert!((u0_found - u0).abs() < 1e-4, "u0 {}", u0_found);
+        assert!((v0_found - v0).abs() < 1e-4, "v0 {}", v0_found);
+    }
+}
@@ -0,0 +1,291 @@
+//! Calibration the proxy loads at startup
+//!
+//! Written by the calibration subcommands as plain `key = value` lines:
+//!
+//! ```text
+//! # Lines starting with # are comments
+//! depth.size = 320x240
+//! depth.intrinsics = fx fy u0 v0 k1 k2 k3 p1 p2
+//! depth.rms = 0.18
+//! rgb.size = 640x480
+//! rgb.intrinsics = fx fy u0 v0 k1 k2 k3 p1 p2
+//! rgb.rms = 0.32
+//! extrinsics.rotation = r00 r01 r02 r10 r11 r12 r20 r21 r22
+//! extrinsics.translation = tx ty tz
+//! ```
+//!
+//! Every section is optional, missing ones fall back to the built-in values.
+//! Sizes are the image size the intrinsics were measured at, and RMS is the
+//! reprojection error of the calibration in pixels.
+//!
+//! Each camera unit is calibrated on its own, the file for a camera with a
+//! serial number lives in `calibration/<serial>.txt`.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::camera::{
+    device_info::DeviceInfo,
+    intrinsics::{CameraIntrinsics, DEFAULT_INTRINSICS_SHAPE},
+    registration::Extrinsics,
+};
+
+/// Where the proxy looks for a calibration when none is given
+pub const DEFAULT_CALIBRATION_PATH: &str = "calibration.txt";
+/// Directory holding one calibration per camera serial number
+pub const DEFAULT_CALIBRATION_DIR: &str = "calibration";
+
+/// Calibration of one camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraCalibration {
+    pub intrinsics: CameraIntrinsics,
+    /// Image size the intrinsics are for, as (height, width)
+    pub shape: (usize, usize),
+    /// Reprojection error in pixels, unknown for factory values
+    pub rms: Option<f64>,
+}
+
+/// Everything a calibration file can hold
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Calibration {
+    /// Depth camera, shared with the IR image
+    pub depth: Option<CameraCalibration>,
+    pub rgb: Option<CameraCalibration>,
+    /// Pose of the RGB camera relative to the depth camera
+    pub extrinsics: Option<Extrinsics>,
+}
+
+impl Calibration {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    /// Calibration file for the camera with this serial number
+    pub fn device_path(serial: &str) -> PathBuf {
+        // Keep odd serials from escaping the directory
+        let name: String = serial
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .collect();
+        Path::new(DEFAULT_CALIBRATION_DIR).join(format!("{}.txt", name))
+    }
+
+    /// Picks the calibration for a camera, with a description of where it
+    /// came from
+    ///
+    /// Tries the camera's own file, then `calibration.txt`, then the lens
+    /// the camera reports and finally the built-in values.
+    pub fn for_device(
+        device: Option<&DeviceInfo>,
+    ) -> Result<(Self, String), Box<dyn std::error::Error>> {
+        let mut paths = Vec::new();
+        if let Some(device) = device {
+            paths.push(Self::device_path(&device.serial));
+        }
+        paths.push(PathBuf::from(DEFAULT_CALIBRATION_PATH));
+
+        for path in paths {
+            if path.exists() {
+                return Ok((Self::load(&path)?, path.display().to_string()));
+            }
+        }
+
+        if let Some(lens) = device.and_then(|device| device.lens) {
+            let calibration = Self {
+                depth: Some(CameraCalibration {
+                    intrinsics: lens,
+                    shape: DEFAULT_INTRINSICS_SHAPE,
+                    rms: None,
+                }),
+                ..Self::default()
+            };
+            return Ok((calibration, "factory lens parameters".to_string()));
+        }
+
+        Ok((Self::default(), "built-in defaults".to_string()))
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut depth = CameraFields::default();
+        let mut rgb = CameraFields::default();
+        let mut rotation = None;
+        let mut translation = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected key = value", number + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+            let error =
+                |e: Box<dyn std::error::Error>| format!("line {}: {}: {}", number + 1, key, e);
+
+            match key.split_once('.') {
+                Some(("depth", field)) => depth.set(field, value).map_err(error)?,
+                Some(("rgb", field)) => rgb.set(field, value).map_err(error)?,
+                Some(("extrinsics", "rotation")) => {
+                    rotation = Some(numbers::<9>(value).map_err(error)?)
+                }
+                Some(("extrinsics", "translation")) => {
+                    translation = Some(numbers::<3>(value).map_err(error)?)
+                }
+                _ => return Err(format!("line {}: unknown key {}", number + 1, key).into()),
+            }
+        }
+
+        let extrinsics = match (rotation, translation) {
+            (Some(r), Some(translation)) => Some(Extrinsics {
+                rotation: [[r[0], r[1], r[2]], [r[3], r[4], r[5]], [r[6], r[7], r[8]]],
+                translation,
+            }),
+            (None, None) => None,
+            _ => return Err("extrinsics need both rotation and translation".into()),
+        };
+
+        Ok(Self {
+            depth: depth.build("depth")?,
+            rgb: rgb.build("rgb")?,
+            extrinsics,
+        })
+    }
+
+    fn to_text(self) -> String {
+        let mut text = String::from("# raspi-proxy calibration\n");
+
+        for (name, camera) in [("depth", &self.depth), ("rgb", &self.rgb)] {
+            if let Some(camera) = camera {
+                let i = &camera.intrinsics;
+                let _ = writeln!(
+                    text,
+                    "{}.size = {}x{}",
+                    name, camera.shape.1, camera.shape.0
+                );
+                let _ = writeln!(
+                    text,
+                    "{}.intrinsics = {} {} {} {} {} {} {} {} {}",
+                    name, i.fx, i.fy, i.u0, i.v0, i.k1, i.k2, i.k3, i.p1, i.p2
+                );
+                if let Some(rms) = camera.rms {
+                    let _ = writeln!(text, "{}.rms = {}", name, rms);
+                }
+            }
+        }
+
+        if let Some(ref extrinsics) = self.extrinsics {
+            let r = &extrinsics.rotation;
+            let t = &extrinsics.translation;
+            let _ = writeln!(
+                text,
+                "extrinsics.rotation = {} {} {} {} {} {} {} {} {}",
+                r[0][0], r[0][1], r[0][2], r[1][0], r[1][1], r[1][2], r[2][0], r[2][1], r[2][2]
+            );
+            let _ = writeln!(text, "extrinsics.translation = {} {} {}", t[0], t[1], t[2]);
+        }
+
+        text
+    }
+}
+
+/// Fields of one camera's section, all needed but the RMS
+#[derive(Default)]
+struct CameraFields {
+    shape: Option<(usize, usize)>,
+    intrinsics: Option<[f64; 9]>,
+    rms: Option<f64>,
+}
+
+impl CameraFields {
+    fn set(&mut self, field: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match field {
+            "size" => {
+                let (width, height) = value.split_once('x').ok_or("expected WIDTHxHEIGHT")?;
+                self.shape = Some((height.trim().parse()?, width.trim().parse()?));
+            }
+            "intrinsics" => self.intrinsics = Some(numbers::<9>(value)?),
+            "rms" => self.rms = Some(value.parse()?),
+            _ => return Err("unknown field".into()),
+        }
+        Ok(())
+    }
+
+    fn build(self, name: &str) -> Result<Option<CameraCalibration>, Box<dyn std::error::Error>> {
+        match (self.shape, self.intrinsics) {
+            (Some(shape), Some(i)) => Ok(Some(CameraCalibration {
+                intrinsics: CameraIntrinsics {
+                    fx: i[0],
+                    fy: i[1],
+                    u0: i[2],
+                    v0: i[3],
+                    k1: i[4],
+                    k2: i[5],
+                    k3: i[6],
+                    p1: i[7],
+                    p2: i[8],
+                },
+                shape,
+                rms: self.rms,
+            })),
+            (None, None) if self.rms.is_none() => Ok(None),
+            _ => Err(format!("{} needs both size and intrinsics", name).into()),
+        }
+    }
+}
+
+/// Parses exactly N whitespace separated numbers
+fn numbers<const N: usize>(value: &str) -> Result<[f64; N], Box<dyn std::error::Error>> {
+    let values = value
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<f64>, _>>()?;
+
+    values
+        .try_into()
+        .map_err(|values: Vec<f64>| format!("expected {} numbers, got {}", N, values.len()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{intrinsics::DEFAULT_INTRINSICS, registration::DEFAULT_EXTRINSICS};
+
+    #[test]
+    fn text_round_trip() {
+        let calibration = Calibration {
+            depth: Some(CameraCalibration {
+                intrinsics: DEFAULT_INTRINSICS,
+                shape: DEFAULT_INTRINSICS_SHAPE,
+                rms: None,
+            }),
+            rgb: Some(CameraCalibration {
+                intrinsics: CameraIntrinsics::new(
+                    520.1, 518.3, 322.5, 236.8, 0.08, -0.15, 0., 0.001, -0.0005,
+                ),
+                shape: (480, 640),
+                rms: Some(0.32),
+            }),
+            extrinsics: Some(DEFAULT_EXTRINSICS),
+        };
+
+        let empty = Calibration::default();
+
+        assert_eq!(Calibration::parse(&calibration.to_text()).unwrap(), calibration);
+        assert_eq!(Calibration::parse(&empty.to_text()).unwrap(), empty);
+    }
+}
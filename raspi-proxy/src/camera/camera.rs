@@ -1,251 +1,485 @@
 use std::{
     sync::{
-        Arc, Mutex
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
     },
     thread,
+    time::{Duration, Instant},
 };
 
-use std::io::Cursor;
-
-use byteorder::{LittleEndian, ReadBytesExt};
-use ndarray::Array2;
-
-use crate::camera::{fetch_frame::{decode_frame, frame_config_encode, normalize, ProcessedFrames}, intrinsics::{depth_to_point_cloud, DEFAULT_INTRINSICS}, PointArr};
-
-
-// Constants (replace with your actual values)
-const HOST: &str = "192.168.233.1";
-const PORT: u16 = 80;
-
-pub struct PrevFrames {
-    pub depth: Array2<u16>,
-    pub status: Array2<u16>,
-}
+use crate::camera::{
+    calibration::Calibration,
+    connection::{Backoff, ConnectionState},
+    fetch_frame::{
+        decode_frame, decode_frame_config, decode_frame_header, FrameConfig, FrameMessage,
+    },
+    filters::FilterConfig,
+    pipeline::Mailbox,
+    projector::Projector,
+    source::{FrameSource, SipeedHttpSource},
+    PointCloud,
+};
 
-impl Default for PrevFrames {
-    fn default() -> Self {
-        Self {
-            depth: Array2::from_elem((240, 320), 0),
-            status: Array2::from_elem((240, 320), 0),
-        }
-    }
+/// How long `get_points` waits for a frame it has not sent yet
+const NEW_FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long the camera may repeat the same frame before it counts as stalled
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Consecutive failures after which the camera counts as disconnected
+const DISCONNECT_AFTER: u32 = 3;
+
+/// Bounds of the delay between retries after a failure
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// First delay before asking again after a bad or already seen frame, it
+/// doubles up to `MAX_BACKOFF` until a new frame arrives
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Shortest time between two attempts to send the same config
+const CONFIG_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// State shared between `SipeedCamera` and its pipeline threads
+///
+/// Frames flow fetch -> decode -> project through the mailboxes, each stage
+/// on its own thread. Every mailbox keeps only the newest message, so a slow
+/// stage skips frames instead of holding up the one before it.
+struct Shared {
+    config: Mutex<FrameConfig>,
+    filter_config: Mutex<FilterConfig>,
+    calibration: Mutex<Calibration>,
+    /// Whether `calibration` was given, instead of looked up for each camera
+    fixed_calibration: AtomicBool,
+    /// Messages for the fetch thread, only `Shutdown` for now
+    control: Mailbox<FrameMessage>,
+    state: Mutex<ConnectionState>,
+    dropped_frames: AtomicU64,
+    raw_frames: Mailbox<FrameMessage>,
+    decoded_frames: Mailbox<FrameMessage>,
+    clouds: Mailbox<PointCloud>,
 }
 
 pub struct SipeedCamera {
-    frames: Arc<Mutex<ProcessedFrames>>,
-    prev_frames: PrevFrames,
-    thread_handle: Option<thread::JoinHandle<()>>,
-    // point_cloud: LivePointView,
+    shared: Arc<Shared>,
+    /// Set on drop so the source stops waiting for a frame
+    shutdown: Arc<AtomicBool>,
+    thread_handles: Vec<thread::JoinHandle<()>>,
 }
 
 impl Default for SipeedCamera {
     fn default() -> Self {
-        // Shared state for the latest processed frames
-        let frames = Arc::new(Mutex::new(ProcessedFrames::default()));
-
-        let frames_clone = Arc::clone(&frames);
-        let decoder_handle = thread::spawn(move || {
-            loop {
-                match fetch_frame() {
-                    Ok(frame_data) => match decode_frame(&frame_data) {
-                        Ok(processed) => {
-                            *frames_clone.lock().unwrap() = processed;
-                        }
-                        Err(e) => warn!("Error decoding frame: {}", e),
-                    },
-                    Err(e) => warn!("Error fetching frame: {}", e),
-                }
-            }
+        Self::new(Box::new(SipeedHttpSource::default()))
+    }
+}
+
+impl SipeedCamera {
+    /// Starts the pipeline threads reading frames from `source`
+    pub fn new(mut source: Box<dyn FrameSource>) -> Self {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        source.watch_shutdown(Arc::clone(&shutdown));
+
+        let shared = Arc::new(Shared {
+            config: Mutex::new(FrameConfig::default()),
+            filter_config: Mutex::new(FilterConfig::default()),
+            calibration: Mutex::new(Calibration::default()),
+            fixed_calibration: AtomicBool::new(false),
+            control: Mailbox::default(),
+            state: Mutex::new(ConnectionState::Connecting),
+            dropped_frames: AtomicU64::new(0),
+            raw_frames: Mailbox::default(),
+            decoded_frames: Mailbox::default(),
+            clouds: Mailbox::default(),
         });
 
+        let thread_handles = vec![
+            spawn_stage("fetch", &shared, move |shared| fetch_loop(source, shared)),
+            spawn_stage("decode", &shared, decode_loop),
+            spawn_stage("project", &shared, project_loop),
+        ];
+
         Self {
-            frames,
-            thread_handle: Some(decoder_handle),
-            prev_frames: PrevFrames::default(),
-            // point_cloud: LivePointView::default(),
+            shared,
+            shutdown,
+            thread_handles,
         }
     }
-}
 
-impl SipeedCamera {
-    pub fn get_points(&mut self) -> Option<PointArr> {
-        let frames_lock = self.frames.lock().unwrap();
-        let ref frames = *frames_lock;
+    /// Config the camera thread sends to the camera
+    pub fn config(&self) -> FrameConfig {
+        *self.shared.config.lock().unwrap()
+    }
+
+    /// Replaces the config, applied from the next frame on
+    pub fn set_config(&self, config: FrameConfig) {
+        *self.shared.config.lock().unwrap() = config;
+    }
+
+    /// Depth filters the projector thread runs
+    pub fn filter_config(&self) -> FilterConfig {
+        *self.shared.filter_config.lock().unwrap()
+    }
+
+    /// Replaces the depth filters, applied from the next frame on
+    pub fn set_filter_config(&self, config: FilterConfig) {
+        *self.shared.filter_config.lock().unwrap() = config;
+    }
+
+    /// Calibration the projector thread uses
+    pub fn calibration(&self) -> Calibration {
+        *self.shared.calibration.lock().unwrap()
+    }
+
+    /// Replaces the calibration, applied from the next frame on
+    ///
+    /// Until this is called the calibration is looked up for each camera
+    /// that connects, see `Calibration::for_device`.
+    pub fn set_calibration(&self, calibration: Calibration) {
+        let mut current = self.shared.calibration.lock().unwrap();
+        self.shared.fixed_calibration.store(true, Ordering::Relaxed);
+        *current = calibration;
+    }
 
-        // let prev_status_lock = self.prev_status.lock();
-        // let ref prev_status = *prev_status_lock;
+    /// Frames the camera produced that never reached the camera thread
+    pub fn dropped_frames(&self) -> u64 {
+        self.shared.dropped_frames.load(Ordering::Relaxed)
+    }
 
-        let mut processed = false;
+    /// Why frames are or are not coming in
+    pub fn connection_state(&self) -> ConnectionState {
+        *self.shared.state.lock().unwrap()
+    }
 
-        // Display depth image
-        if let Some(ref depth) = frames.depth {
-            self.prev_frames.depth = (&self.prev_frames.depth + depth) / 2;
+    /// Takes the newest point cloud, waiting for one if it was already sent
+    pub fn get_points(&self) -> Option<PointCloud> {
+        let cloud = self.shared.clouds.recv_timeout(NEW_FRAME_TIMEOUT);
 
-            let depth_viz = normalize(&self.prev_frames.depth);
-            processed = true;
+        if cloud.is_none() {
+            trace!("No new frame within {:?}", NEW_FRAME_TIMEOUT);
         }
 
-        // // Display IR image
-        // if let Some(ref ir) = frames.ir {
-        //     let ir_viz = normalize(ir);
-        //     ui.heading("IR");
-        //     image_widget(ui, "ir_img", &ir_viz, [320.0, 240.0]);
-        //     processed = true;
-        // }
-
-        // Display status image if available
-        if let Some(ref status) = frames.status {
-            self.prev_frames.status = (&self.prev_frames.status + (2 - status)) / 2;
-            let status_viz = normalize(&self.prev_frames.status);
-            processed = true;
+        cloud
+    }
+}
+
+impl Drop for SipeedCamera {
+    /// Stops the pipeline after the request in flight and waits for it
+    fn drop(&mut self) {
+        debug!("Shutting down camera pipeline");
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.shared.control.send(FrameMessage::Shutdown);
+
+        for handle in self.thread_handles.drain(..) {
+            let name = handle.thread().name().unwrap_or("camera").to_string();
+            if handle.join().is_err() {
+                error!("Thread {} panicked", name);
+            }
         }
 
-        // Display RGB image if available
-        if let Some(ref rgb) = frames.rgb {
-            let rgb_viz = rgb.as_slice().unwrap();
-            let size = match rgb.dim().1 {
-                640 => [640.0, 480.0],
-                800 => [800.0, 600.0],
-                _ => [640.0, 480.0], // Default
-            };
-            processed = true;
+        info!("Camera pipeline stopped");
+    }
+}
+
+fn spawn_stage(
+    name: &str,
+    shared: &Arc<Shared>,
+    stage: impl FnOnce(&Shared) + Send + 'static,
+) -> thread::JoinHandle<()> {
+    let shared = Arc::clone(shared);
+    thread::Builder::new()
+        .name(format!("camera-{}", name))
+        .spawn(move || stage(&shared))
+        .expect("Failed to spawn camera thread")
+}
+
+/// Fetches raw frames and tracks the connection until told to shut down
+fn fetch_loop(mut source: Box<dyn FrameSource>, shared: &Shared) {
+    let mut config = ConfigSync::default();
+    // Whether a wrong echoed config was reported since the last right one
+    let mut echo_warned = false;
+    // Whether the calibration was looked up since the camera (re)connected
+    let mut identified = false;
+    let mut last_frame_id: Option<u64> = None;
+    let mut last_new_frame = Instant::now();
+
+    let mut failures = 0;
+    let mut backoff = Backoff::new(MIN_BACKOFF, MAX_BACKOFF);
+    // Answers in a row that brought no new frame, and the delay between them
+    let mut idle = 0;
+    let mut poll = Backoff::new(MIN_POLL_INTERVAL, MAX_BACKOFF);
+
+    loop {
+        // Wait out the backoff, waking early for a shutdown
+        let control = if failures > 0 {
+            shared.control.recv_timeout(backoff.next_delay())
+        } else if idle > 0 {
+            shared.control.recv_timeout(poll.next_delay())
+        } else {
+            shared.control.try_recv()
+        };
+
+        if let Some(FrameMessage::Shutdown) = control {
+            shared.raw_frames.send(FrameMessage::Shutdown);
+            return;
         }
 
-        if let Some(ref status) = frames.status {
-            if let Some(ref rgb) = frames.rgb {
-                let mut points: PointArr = Vec::new();
+        // Only the first retry after a disconnect counts as connecting, the
+        // state then stays put until a frame arrives
+        if failures == DISCONNECT_AFTER {
+            set_state(shared, ConnectionState::Connecting);
+        }
+
+        let frame_data = match fetch(&mut *source, shared, &mut config) {
+            Ok(frame_data) => frame_data,
+            Err(e) => {
+                // Assume the camera reconnects without its config, and
+                // maybe as another unit
+                config.reset();
+                identified = false;
+                failures += 1;
+
+                if failures == 1 {
+                    warn!("Error reading camera: {}", e);
+                } else {
+                    debug!("Error reading camera ({} in a row): {}", failures, e);
+                }
 
-                for i in 0..(320 * 240) {
-                    let x = i / 240;
-                    let y = i % 320;
+                let state = *shared.state.lock().unwrap();
+                if failures >= DISCONNECT_AFTER {
+                    set_state(shared, ConnectionState::Disconnected);
+                } else if state == ConnectionState::Streaming {
+                    set_state(shared, ConnectionState::Stalled);
+                }
+                continue;
+            }
+        };
 
-                    // println!("{:?}", status.get((x, y)));
+        // Both delays only start over once a new frame arrives
+        failures = 0;
+        idle += 1;
 
-                    let status = status.get((x, y));
+        if !identified {
+            identify(&mut *source, shared);
+            identified = true;
+        }
 
-                    if status.is_none() || *status.unwrap() != 0 {
-                        continue;
+        let (frame_id, _) = match decode_frame_header(&frame_data) {
+            Ok(header) => header,
+            Err(e) => {
+                // The camera is reachable, the frame itself was bad
+                if idle == 1 {
+                    warn!("Error decoding frame: {}", e);
+                } else {
+                    debug!("Error decoding frame ({} in a row): {}", idle, e);
+                }
+                continue;
+            }
+        };
+
+        // The camera may have reset or dropped the config
+        if source.honours_config()
+            && let Some(applied) = config.applied
+        {
+            match decode_frame_config(&frame_data) {
+                Ok(echoed) if applied.same_layout(&echoed) => echo_warned = false,
+                Ok(echoed) => {
+                    if !echo_warned {
+                        warn!("Camera echoed config {:?}, expected {:?}", echoed, applied);
+                        echo_warned = true;
                     }
-
-                    // let prev_status = prev_status.get((x, y));
-
-                    // if prev_status.is_none() || *prev_status.unwrap() != 0 {
-                    //     continue;
-                    // }
-
-                    // if *status.get((x, y)).unwrap() != 0u16 {
-                    //     continue;
-                    // }
-                    //
-
-                    let (r, g, b) = if let Some((rgbx, rgby)) = scale_shift_rgb_xy(x, y) {
-                        (
-                            *rgb.get((rgbx, rgby, 0)).unwrap() as u8,
-                            *rgb.get((rgbx, rgby, 1)).unwrap() as u8,
-                            *rgb.get((rgbx, rgby, 2)).unwrap() as u8,
-                        )
-                    } else {
-                        (255, 255, 255)
-                    };
-
-                    let d = self.prev_frames.depth.get((x, y)).unwrap();
-
-                    let (x, y, z) = depth_to_point_cloud(
-                        x as i32,
-                        y as i32,
-                        d + 50,
-                        &DEFAULT_INTRINSICS,
-                    );
-
-                    points.push((x, y, z, r, g, b))
+                    config.forget();
                 }
+                Err(e) => {
+                    if !echo_warned {
+                        warn!("Cannot read the config the camera echoed: {}", e);
+                        echo_warned = true;
+                    }
+                }
+            }
+        }
 
-                return Some(points)
+        // Replays repeat ids when they loop, a camera only when it has no
+        // new frame yet
+        if source.live_frame_ids() && last_frame_id == Some(frame_id) {
+            // The camera answered with a frame we already have
+            if last_new_frame.elapsed() > STALL_TIMEOUT {
+                set_state(shared, ConnectionState::Stalled);
             }
+            continue;
         }
 
-        None
+        match last_frame_id {
+            Some(last) if frame_id > last + 1 => {
+                let dropped = frame_id - last - 1;
+                debug!("Dropped {} frames before frame {}", dropped, frame_id);
+                shared.dropped_frames.fetch_add(dropped, Ordering::Relaxed);
+            }
+            Some(last) if frame_id < last => {
+                info!(
+                    "Frame id went from {} back to {}, camera restarted",
+                    last, frame_id
+                );
+            }
+            _ => {}
+        }
+        last_frame_id = Some(frame_id);
+        last_new_frame = Instant::now();
+        idle = 0;
+        poll.reset();
+        backoff.reset();
+
+        set_state(shared, ConnectionState::Streaming);
 
-        // self.point_cloud.render(ui);
+        if shared.raw_frames.send(FrameMessage::RawFrame(frame_data)) {
+            trace!("Decoder busy, skipped a raw frame");
+        }
     }
 }
 
-fn scale_shift_rgb_xy(x: usize, y: usize) -> Option<(usize, usize)> {
-    static X_OFFSET: f32 = 30.;
-    static Y_OFFSET: f32 = 22.;
-    static SCAME: f32 = 1.75;
-
-    let x = ((x as f32 + X_OFFSET) * SCAME) as usize;
-    let y = ((y as f32 + Y_OFFSET) * SCAME) as usize;
-
-    if y >= 640 {
-        return None;
+/// Looks up the calibration for the connected camera, unless one was given
+fn identify(source: &mut dyn FrameSource, shared: &Shared) {
+    if shared.fixed_calibration.load(Ordering::Relaxed) {
+        return;
     }
-    if x >= 480 {
-        return None;
-    }
-
-    // println!("{}, {}", x, y);
 
-    Some((x as usize, y as usize))
-}
+    let device = source.device_info().unwrap_or_else(|e| {
+        warn!("Error reading device info: {}", e);
+        None
+    });
 
-fn fetch_frame() -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    is_success(&frame_config_encode(1, 0, 255, 0, 2, 7, 1, 0, 0))?;
+    let (calibration, origin) = match Calibration::for_device(device.as_ref()) {
+        Ok(found) => found,
+        Err(e) => {
+            error!("Failed to load calibration: {}", e);
+            (Calibration::default(), "built-in defaults".to_string())
+        }
+    };
 
+    // Checked again under the lock in case one was given meanwhile
+    let mut current = shared.calibration.lock().unwrap();
+    if shared.fixed_calibration.load(Ordering::Relaxed) {
+        return;
+    }
 
-    let url = format!("http://{}:{}/getdeep", HOST, PORT);
+    match device {
+        Some(device) => info!("Camera {}, using calibration from {}", device.serial, origin),
+        None => info!("Camera has no serial number, using calibration from {}", origin),
+    }
+    *current = calibration;
+}
 
-    trace!("Fetching images from: {}", url);
+/// What the camera was told, so `/set_cfg` is only sent when needed
+#[derive(Default)]
+struct ConfigSync {
+    /// Config the camera last acknowledged, None until it is (re)sent
+    applied: Option<FrameConfig>,
+    /// Last time `/set_cfg` was sent, successful or not
+    last_attempt: Option<Instant>,
+}
 
-    let response = ureq::get(url).call()?;
+impl ConfigSync {
+    /// Sends `config` unless the camera has it, or was sent it too recently
+    fn apply(
+        &mut self,
+        source: &mut dyn FrameSource,
+        config: &FrameConfig,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.applied == Some(*config) {
+            return Ok(());
+        }
+        if let Some(last) = self.last_attempt
+            && last.elapsed() < CONFIG_RETRY_DELAY
+        {
+            return Ok(());
+        }
 
-    if response.status() != 200 {
-        return Err(format!("Failed to get frame: HTTP {}", response.status()).into());
+        self.last_attempt = Some(Instant::now());
+        source.apply_config(config)?;
+        debug!("Applied camera config {:?}", config);
+        self.applied = Some(*config);
+        Ok(())
     }
 
-    trace!("Got deep image");
-    let deep_img = response.into_body().read_to_vec()?;
-    trace!("Length={}", deep_img.len());
-
-    // Parse frame ID and timestamp
-    if deep_img.len() >= 16 {
-        let mut cursor = Cursor::new(&deep_img[0..16]);
-        let frame_id = cursor.read_u64::<LittleEndian>()?;
-        let stamp_msec = cursor.read_u64::<LittleEndian>()?;
-        trace!(
-            "Frame ID: {}, Timestamp: {:.3}s",
-            frame_id,
-            stamp_msec as f64 / 1000.0
-        );
+    /// Makes `apply` send the config again once the retry delay is up
+    fn forget(&mut self) {
+        self.applied = None;
     }
 
-    return Ok(deep_img);
+    /// Makes the next `apply` send the config right away, for a camera that
+    /// went away and may come back without it
+    fn reset(&mut self) {
+        self.applied = None;
+        self.last_attempt = None;
+    }
 }
 
-fn is_success(data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-    let url = format!("http://{}:{}/set_cfg", HOST, PORT);
+/// Applies the config if needed, then fetches one raw frame
+///
+/// A config the camera refused is retried after `CONFIG_RETRY_DELAY`, frames
+/// keep coming with the old one in between.
+fn fetch(
+    source: &mut dyn FrameSource,
+    shared: &Shared,
+    config: &mut ConfigSync,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let wanted = *shared.config.lock().unwrap();
+
+    if let Err(e) = config.apply(source, &wanted) {
+        warn!("Camera refused config: {}", e);
+    }
 
-    trace!("Sending request to: {}", url);
+    source.fetch_frame()
+}
 
-    let response = ureq::post(url).send(data.to_vec())?;
-    if response.status() == 200 {
-        return Ok(());
-    } else {
-        return Err(format!("Status code: {}", response.status().to_string()).into());
+/// Decodes payloads and JPEG images
+fn decode_loop(shared: &Shared) {
+    loop {
+        match shared.raw_frames.recv() {
+            FrameMessage::RawFrame(frame_data) => match decode_frame(&frame_data) {
+                Ok(processed) => {
+                    if shared
+                        .decoded_frames
+                        .send(FrameMessage::DecodedFrame(processed))
+                    {
+                        trace!("Projector busy, skipped a decoded frame");
+                    }
+                }
+                Err(e) => warn!("Error decoding frame: {}", e),
+            },
+            FrameMessage::DecodedFrame(_) => unreachable!("decoded frame sent to decoder"),
+            FrameMessage::Shutdown => {
+                shared.decoded_frames.send(FrameMessage::Shutdown);
+                return;
+            }
+        }
     }
 }
 
-// Helper to display images in egui
-// fn image_widget(ui: &mut egui::Ui, id: &str, rgb_data: &[u8], size: [f32; 2]) {
-//     let color_image = egui::ColorImage::from_rgb([size[0] as usize, size[1] as usize], rgb_data);
-
-//     let handle = ui
-//         .ctx()
-//         .load_texture(id, color_image, egui::TextureOptions::LINEAR);
-
-//     let sized_image =
-//         egui::load::SizedTexture::new(handle.id(), egui::vec2(size[0] as f32, size[1] as f32));
+/// Projects decoded frames into point clouds
+fn project_loop(shared: &Shared) {
+    let mut projector = Projector::default();
+
+    loop {
+        match shared.decoded_frames.recv() {
+            FrameMessage::DecodedFrame(frames) => {
+                let config = *shared.filter_config.lock().unwrap();
+                projector.set_calibration(&shared.calibration.lock().unwrap());
+                if let Some(cloud) = projector.project(&config, &frames)
+                    && shared.clouds.send(cloud)
+                {
+                    trace!("No client took the last point cloud");
+                }
+            }
+            FrameMessage::RawFrame(_) => unreachable!("raw frame sent to projector"),
+            FrameMessage::Shutdown => return,
+        }
+    }
+}
 
-//     ui.image(sized_image);
-// }
+fn set_state(shared: &Shared, state: ConnectionState) {
+    let mut current = shared.state.lock().unwrap();
+    if *current != state {
+        info!("Camera {:?} -> {:?}", *current, state);
+        *current = state;
+    }
+}
@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+/// Where the camera thread is with its frame source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Trying to reach the source, nothing received yet
+    Connecting = 0,
+    /// Frames are arriving
+    Streaming = 1,
+    /// The source answered before but frames stopped coming
+    Stalled = 2,
+    /// Repeated failures, retrying with backoff
+    Disconnected = 3,
+}
+
+/// Exponential delay between retries
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self { min, max, next: min }
+    }
+
+    /// Delay before the next retry, doubling each time up to the maximum
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next = self.min;
+    }
+}
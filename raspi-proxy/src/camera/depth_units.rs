@@ -0,0 +1,126 @@
+use crate::camera::fetch_frame::{DeepMode, FrameConfig, DEEP_SHIFT_AUTO};
+
+/// Shift assumed for 8-bit depth when `deep_shift` is left to the firmware
+///
+/// 255 << 4 covers 4 m, the far end of the ToF sensor's range.
+pub const DEFAULT_DEEP_SHIFT: u8 = 4;
+
+/// 8-bit value the camera sends for pixels beyond the shifted range
+const DEPTH8_SATURATED: u16 = 255;
+
+/// Millimetres the camera's depth falls short of the surface
+const SURFACE_OFFSET: u16 = 50;
+
+/// Maps raw depth values of a frame config to millimetres
+///
+/// 16-bit depth is already in millimetres. 8-bit depth is millimetres shifted
+/// right by `deep_shift`, so each step is `1 << deep_shift` mm and saturated
+/// pixels are reported as 0, the same as any other invalid pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthUnits {
+    /// None for 16-bit depth
+    shift: Option<u8>,
+}
+
+impl DepthUnits {
+    pub fn from_config(config: &FrameConfig) -> Self {
+        let shift = match config.deep_mode() {
+            DeepMode::Bits16 => None,
+            DeepMode::Bits8 if config.deep_shift() == DEEP_SHIFT_AUTO => Some(DEFAULT_DEEP_SHIFT),
+            DeepMode::Bits8 => Some(config.deep_shift()),
+        };
+
+        Self { shift }
+    }
+
+    /// Converts a raw depth value into millimetres, 0 for invalid pixels
+    pub fn millimetres(&self, raw: u16) -> u16 {
+        match self.shift {
+            None => raw,
+            Some(_) if raw >= DEPTH8_SATURATED => 0,
+            Some(shift) => (u32::from(raw) << shift).min(u32::from(u16::MAX)) as u16,
+        }
+    }
+
+    /// Distance to the surface for a depth in millimetres
+    ///
+    /// Saturates so 8-bit depth clamped to `u16::MAX` cannot overflow.
+    pub fn surface(millimetres: u16) -> u16 {
+        millimetres.saturating_add(SURFACE_OFFSET)
+    }
+
+    /// Converts millimetres into the raw value the camera would send
+    pub fn raw(&self, millimetres: u16) -> u16 {
+        match self.shift {
+            None => millimetres,
+            Some(shift) => (millimetres >> shift).min(DEPTH8_SATURATED),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::fetch_frame::DEEP_SHIFT_MAX;
+
+    fn units(deep_mode: DeepMode, deep_shift: u8) -> DepthUnits {
+        let config = FrameConfig::builder()
+            .deep_mode(deep_mode)
+            .deep_shift(deep_shift)
+            .build()
+            .unwrap();
+        DepthUnits::from_config(&config)
+    }
+
+    #[test]
+    fn sixteen_bit_depth_is_millimetres() {
+        let units = units(DeepMode::Bits16, DEEP_SHIFT_AUTO);
+
+        assert_eq!(units.millimetres(1234), 1234);
+        assert_eq!(units.millimetres(u16::MAX), u16::MAX);
+        assert_eq!(units.raw(1234), 1234);
+    }
+
+    #[test]
+    fn shift_0_is_millimetres_up_to_254() {
+        let units = units(DeepMode::Bits8, 0);
+
+        assert_eq!(units.millimetres(100), 100);
+        assert_eq!(units.millimetres(254), 254);
+        assert_eq!(units.raw(100), 100);
+        assert_eq!(units.raw(1000), DEPTH8_SATURATED);
+    }
+
+    #[test]
+    fn auto_shift_is_the_default_shift() {
+        let units = units(DeepMode::Bits8, DEEP_SHIFT_AUTO);
+
+        assert_eq!(units.shift, Some(DEFAULT_DEEP_SHIFT));
+        assert_eq!(units.millimetres(100), 1600);
+        assert_eq!(units.raw(1600), 100);
+        assert_eq!(units.raw(1615), 100);
+        assert_eq!(units.raw(5000), DEPTH8_SATURATED);
+    }
+
+    #[test]
+    fn maximum_shift_clamps_to_u16() {
+        let units = units(DeepMode::Bits8, DEEP_SHIFT_MAX);
+
+        assert_eq!(units.millimetres(31), 31 << 11);
+        assert_eq!(units.millimetres(32), u16::MAX);
+        assert_eq!(units.millimetres(254), u16::MAX);
+    }
+
+    #[test]
+    fn saturated_raw_depth_is_invalid() {
+        for shift in [0, DEFAULT_DEEP_SHIFT, DEEP_SHIFT_MAX, DEEP_SHIFT_AUTO] {
+            assert_eq!(units(DeepMode::Bits8, shift).millimetres(DEPTH8_SATURATED), 0);
+        }
+    }
+
+    #[test]
+    fn surface_offset_saturates() {
+        assert_eq!(DepthUnits::surface(1000), 1000 + SURFACE_OFFSET);
+        assert_eq!(DepthUnits::surface(u16::MAX), u16::MAX);
+    }
+}
@@ -0,0 +1,154 @@
+//! Identity of the connected camera
+//!
+//! `/getinfo` is read as a flat JSON object. Only the serial number `sn` is
+//! required, the lens coefficients `fx`, `fy`, `u0`, `v0`, `k1`, `k2`, `k3`,
+//! `p1` and `p2` are read when all of them are present.
+//!
+//! This is the format `DeviceInfo::to_json` writes for the mock camera. It
+//! has not been checked against a response from real camera firmware, so
+//! anything else, like nested objects, arrays or escape sequences, is an
+//! error rather than a guess. The calibration then falls back to a file or
+//! the built-in defaults.
+
+use crate::camera::intrinsics::CameraIntrinsics;
+
+/// Lens coefficient fields in `CameraIntrinsics::new` order
+const LENS_FIELDS: [&str; 9] = ["fx", "fy", "u0", "v0", "k1", "k2", "k3", "p1", "p2"];
+
+/// What a camera says about itself
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    pub serial: String,
+    /// Lens coefficients measured at the factory, for the full 320x240 image
+    pub lens: Option<CameraIntrinsics>,
+}
+
+impl DeviceInfo {
+    /// Parses a `/getinfo` response, ignoring fields it does not know
+    pub fn parse(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let body = text
+            .trim()
+            .strip_prefix('{')
+            .and_then(|body| body.strip_suffix('}'))
+            .ok_or("Device info is not a JSON object")?;
+
+        // Escaped quotes would throw off where strings end
+        if body.contains('\\') {
+            return Err("Device info has escape sequences, which are not supported".into());
+        }
+
+        let mut fields = Vec::new();
+        for field in split_unquoted(body, ',') {
+            if field.trim().is_empty() {
+                continue;
+            }
+
+            let (key, value) = match split_unquoted(field, ':')[..] {
+                [key, _, ..] => (unquote(key), field[key.len() + 1..].trim()),
+                _ => return Err(format!("Malformed device info field {}", field.trim()).into()),
+            };
+            if value.starts_with(['{', '[']) {
+                return Err(format!("Device info field {} is not a string or number", key).into());
+            }
+
+            fields.push((key, unquote(value)));
+        }
+        let field = |name: &str| fields.iter().find(|(key, _)| *key == name).map(|(_, v)| *v);
+
+        let serial = field("sn").ok_or("Device info has no serial number")?;
+
+        let lens = LENS_FIELDS
+            .iter()
+            .map(|name| field(name)?.parse().ok())
+            .collect::<Option<Vec<f64>>>()
+            .map(|p| CameraIntrinsics::new(p[0], p[1], p[2], p[3], p[4], p[5], p[6], p[7], p[8]));
+
+        Ok(Self {
+            serial: serial.to_string(),
+            lens,
+        })
+    }
+
+    /// The `/getinfo` response for this device
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![format!("\"sn\":\"{}\"", self.serial)];
+
+        if let Some(ref lens) = self.lens {
+            let values = [
+                lens.fx, lens.fy, lens.u0, lens.v0, lens.k1, lens.k2, lens.k3, lens.p1, lens.p2,
+            ];
+            for (name, value) in LENS_FIELDS.iter().zip(values) {
+                fields.push(format!("\"{}\":{}", name, value));
+            }
+        }
+
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// Splits `text` at each `separator` outside of quoted strings
+fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+
+    for (i, c) in text.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            parts.push(&text[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    parts.push(&text[start..]);
+
+    parts
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::intrinsics::DEFAULT_INTRINSICS;
+
+    #[test]
+    fn parse_keeps_separators_inside_strings() {
+        let info = DeviceInfo::parse(r#"{"model":"A075, rev: 2","sn":"ab:cd,1"}"#).unwrap();
+
+        assert_eq!(info.serial, "ab:cd,1");
+        assert_eq!(info.lens, None);
+    }
+
+    #[test]
+    fn parse_rejects_nested_values() {
+        for text in [r#"{"sn":"1","lens":[1,2]}"#, r#"{"sn":"1","lens":{"fx":1}}"#] {
+            let error = DeviceInfo::parse(text).unwrap_err();
+
+            assert_eq!(error.to_string(), "Device info field lens is not a string or number");
+        }
+    }
+
+    #[test]
+    fn parse_rejects_escape_sequences() {
+        let error = DeviceInfo::parse(r#"{"sn":"a\",\"b"}"#).unwrap_err();
+
+        assert_eq!(error.to_string(), "Device info has escape sequences, which are not supported");
+    }
+
+    #[test]
+    fn parse_reads_its_own_json() {
+        let info = DeviceInfo {
+            serial: "0123".to_string(),
+            lens: Some(DEFAULT_INTRINSICS),
+        };
+
+        assert_eq!(DeviceInfo::parse(&info.to_json()).unwrap(), info);
+    }
+}
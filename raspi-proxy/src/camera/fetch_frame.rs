@@ -1,99 +1,408 @@
 use byteorder::{LittleEndian, ReadBytesExt};
 use ndarray::{Array2, Array3};
-use std::io::Cursor;
+use std::{
+    io::Cursor,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
+use crate::camera::depth_units::DepthUnits;
+
+#[derive(Default)]
 pub struct ProcessedFrames {
+    /// Frame counter kept by the camera
+    pub frame_id: u64,
+    /// Capture time on the camera's clock
+    pub stamp_msec: u64,
+    /// Size of the depth, IR and status images
+    pub resolution: Resolution,
     pub depth: Option<Array2<u16>>,
     pub ir: Option<Array2<u16>>,
     pub status: Option<Array2<u16>>,
     pub rgb: Option<Array3<u8>>,
 }
 
-impl Default for ProcessedFrames {
-    fn default() -> Self {
-        Self {
-            depth: None,
-            ir: None,
-            status: None,
-            rgb: None,
-        }
-    }
-}
-
 // Messages between threads
+#[allow(clippy::large_enum_variant)]
 pub enum FrameMessage {
     RawFrame(Vec<u8>),
     DecodedFrame(ProcessedFrames),
     Shutdown,
 }
 
-// Frame data structures
-#[allow(dead_code)]
+/// `deep_shift` value that leaves the 8-bit depth shift up to the firmware
+pub const DEEP_SHIFT_AUTO: u8 = 255;
+
+/// Largest explicit `deep_shift` the firmware accepts
+pub const DEEP_SHIFT_MAX: u8 = 11;
+
+/// Declares a `u8`-backed camera setting and its wire conversions
+macro_rules! config_enum {
+    ($(#[$meta:meta])* $name:ident { $($(#[$vmeta:meta])* $variant:ident = $value:literal,)+ }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $($(#[$vmeta])* $variant = $value,)+
+        }
+
+        impl TryFrom<u8> for $name {
+            type Error = Box<dyn std::error::Error>;
+
+            fn try_from(value: u8) -> Result<Self, Self::Error> {
+                match value {
+                    $($value => Ok(Self::$variant),)+
+                    _ => Err(format!("Invalid {}: {}", stringify!($name), value).into()),
+                }
+            }
+        }
+    };
+}
+
+config_enum!(
+    /// When the camera captures frames
+    TriggerMode {
+        /// Capture is stopped
+        Stop = 0,
+        /// Capture continuously
+        Auto = 1,
+        /// Capture one frame per trigger
+        Single = 2,
+    }
+);
+
+config_enum!(
+    /// Bit depth of the depth image
+    DeepMode {
+        Bits16 = 0,
+        Bits8 = 1,
+    }
+);
+
+config_enum!(
+    /// Bit depth of the IR image
+    IrMode {
+        Bits16 = 0,
+        Bits8 = 1,
+    }
+);
+
+config_enum!(
+    /// Bits per pixel of the status image
+    StatusMode {
+        Bits16 = 0,
+        Bits2 = 1,
+        Bits8 = 2,
+        Bits1 = 3,
+    }
+);
+
+config_enum!(
+    /// Encoding of the RGB image
+    RgbMode {
+        Yuv = 0,
+        Jpeg = 1,
+    }
+);
+
+config_enum!(
+    /// Resolution of the RGB image
+    RgbRes {
+        /// 640x480
+        Vga = 0,
+        /// 800x600
+        Svga = 1,
+    }
+);
+
+impl StatusMode {
+    /// Bits each pixel takes up in the status image
+    pub fn bits(&self) -> usize {
+        match self {
+            StatusMode::Bits16 => 16,
+            StatusMode::Bits2 => 2,
+            StatusMode::Bits8 => 8,
+            StatusMode::Bits1 => 1,
+        }
+    }
+}
+
+impl RgbRes {
+    /// (width, height) of the RGB image in pixels
+    pub fn dimensions(&self) -> (usize, usize) {
+        match self {
+            RgbRes::Vga => (640, 480),
+            RgbRes::Svga => (800, 600),
+        }
+    }
+}
+
+/// Set once an unknown deep data size has been warned about
+static UNKNOWN_DEEP_SIZE_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Size of the depth, IR and status images
+///
+/// The ToF sensor's resolution depends on the camera model and binning, and
+/// is worked out from the size of each frame's depth data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Resolution {
+    /// Full resolution of the MaixSense A075
+    pub const A075: Resolution = Resolution::new(320, 240);
+
+    /// Depth resolutions of MaixSense cameras, including binned modes
+    const KNOWN: [Resolution; 6] = [
+        Resolution::A075,
+        Resolution::new(160, 120),
+        Resolution::new(80, 60),
+        // MaixSense A010
+        Resolution::new(100, 100),
+        Resolution::new(50, 50),
+        Resolution::new(25, 25),
+    ];
+
+    pub const fn new(width: usize, height: usize) -> Self {
+        Self { width, height }
+    }
+
+    pub fn pixels(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// (height, width), the shape of the image arrays
+    pub fn shape(&self) -> (usize, usize) {
+        (self.height, self.width)
+    }
+
+    /// Sizes in bytes of the depth, IR and status images with `config`
+    fn image_sizes(&self, config: &FrameConfig) -> (usize, usize, usize) {
+        (
+            (self.pixels() * 2) >> config.deep_mode as u8,
+            (self.pixels() * 2) >> config.ir_mode as u8,
+            (self.pixels() * config.status_mode.bits()).div_ceil(8),
+        )
+    }
+
+    /// Finds the known resolution whose images add up to `deep_size` bytes
+    ///
+    /// Falls back to the A075's full resolution, which is what a config
+    /// without binning gives, warning about the first size it does not know.
+    pub fn from_deep_size(deep_size: usize, config: &FrameConfig) -> Self {
+        let known = Self::KNOWN.into_iter().find(|resolution| {
+            let (depth, ir, status) = resolution.image_sizes(config);
+            depth + ir + status == deep_size
+        });
+
+        known.unwrap_or_else(|| {
+            if !UNKNOWN_DEEP_SIZE_WARNED.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "No known resolution has {} bytes of deep data, assuming {}x{}",
+                    deep_size,
+                    Self::A075.width,
+                    Self::A075.height
+                );
+            }
+            Self::A075
+        })
+    }
+}
+
+impl Default for Resolution {
+    fn default() -> Self {
+        Resolution::A075
+    }
+}
+
+/// Capture settings sent to `/set_cfg` and echoed back in every frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FrameConfig {
-    trigger_mode: u8,
-    deep_mode: u8,
+    trigger_mode: TriggerMode,
+    deep_mode: DeepMode,
     deep_shift: u8,
-    ir_mode: u8,
-    status_mode: u8,
+    ir_mode: IrMode,
+    status_mode: StatusMode,
     status_mask: u8,
-    rgb_mode: u8,
-    rgb_res: u8,
+    rgb_mode: RgbMode,
+    rgb_res: RgbRes,
     expose_time: i32,
 }
 
-pub struct FramePayload {
-    depth_img: Option<Vec<u8>>,
-    ir_img: Option<Vec<u8>>,
-    status_img: Option<Vec<u8>>,
-    rgb_img: Option<Vec<u8>>,
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self {
+            trigger_mode: TriggerMode::Auto,
+            deep_mode: DeepMode::Bits16,
+            deep_shift: DEEP_SHIFT_AUTO,
+            ir_mode: IrMode::Bits16,
+            status_mode: StatusMode::Bits8,
+            status_mask: 7,
+            rgb_mode: RgbMode::Jpeg,
+            rgb_res: RgbRes::Vga,
+            expose_time: 0,
+        }
+    }
 }
 
-// Helper functions
-fn frame_config_decode(frame_config: &[u8]) -> Result<FrameConfig, Box<dyn std::error::Error>> {
-    if frame_config.len() < 12 {
-        return Err("Frame config data too short".into());
+impl FrameConfig {
+    pub fn builder() -> FrameConfigBuilder {
+        FrameConfigBuilder::default()
     }
+}
 
-    let mut cursor = Cursor::new(frame_config);
+impl FrameConfig {
+    pub fn deep_mode(&self) -> DeepMode {
+        self.deep_mode
+    }
 
-    Ok(FrameConfig {
-        trigger_mode: cursor.read_u8()?,
-        deep_mode: cursor.read_u8()?,
-        deep_shift: cursor.read_u8()?,
-        ir_mode: cursor.read_u8()?,
-        status_mode: cursor.read_u8()?,
-        status_mask: cursor.read_u8()?,
-        rgb_mode: cursor.read_u8()?,
-        rgb_res: cursor.read_u8()?,
-        expose_time: cursor.read_i32::<LittleEndian>()?,
-    })
+    pub fn deep_shift(&self) -> u8 {
+        self.deep_shift
+    }
+
+    pub fn rgb_res(&self) -> RgbRes {
+        self.rgb_res
+    }
+
+    /// Whether `echoed` lays frames out like this config
+    ///
+    /// Only the image modes are compared. The camera may report trigger,
+    /// shift, mask and exposure differently from what it was sent, e.g. the
+    /// exposure it picked in auto mode.
+    pub fn same_layout(&self, echoed: &FrameConfig) -> bool {
+        self.deep_mode == echoed.deep_mode
+            && self.ir_mode == echoed.ir_mode
+            && self.status_mode == echoed.status_mode
+            && self.rgb_mode == echoed.rgb_mode
+            && self.rgb_res == echoed.rgb_res
+    }
+
+    /// Checks the values the enums cannot rule out on their own
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.deep_shift > DEEP_SHIFT_MAX && self.deep_shift != DEEP_SHIFT_AUTO {
+            return Err(format!(
+                "Invalid deep_shift: {} (expected 0-{} or {})",
+                self.deep_shift, DEEP_SHIFT_MAX, DEEP_SHIFT_AUTO
+            )
+            .into());
+        }
+
+        if self.expose_time < 0 {
+            return Err(format!("Invalid expose_time: {}", self.expose_time).into());
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the 12-byte config block used by `/set_cfg` and the frame header
+    ///
+    /// Values are not validated, the camera may echo ones the builder would
+    /// refuse. Call `validate` on configs that come from users.
+    pub fn decode(frame_config: &[u8]) -> Result<FrameConfig, Box<dyn std::error::Error>> {
+        if frame_config.len() < 12 {
+            return Err("Frame config data too short".into());
+        }
+
+        let mut cursor = Cursor::new(frame_config);
+
+        Ok(FrameConfig {
+            trigger_mode: cursor.read_u8()?.try_into()?,
+            deep_mode: cursor.read_u8()?.try_into()?,
+            deep_shift: cursor.read_u8()?,
+            ir_mode: cursor.read_u8()?.try_into()?,
+            status_mode: cursor.read_u8()?.try_into()?,
+            status_mask: cursor.read_u8()?,
+            rgb_mode: cursor.read_u8()?.try_into()?,
+            rgb_res: cursor.read_u8()?.try_into()?,
+            expose_time: cursor.read_i32::<LittleEndian>()?,
+        })
+    }
+
+    /// Encodes the config into the 12-byte block `/set_cfg` expects
+    pub fn encode(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(12);
+        result.push(self.trigger_mode as u8);
+        result.push(self.deep_mode as u8);
+        result.push(self.deep_shift);
+        result.push(self.ir_mode as u8);
+        result.push(self.status_mode as u8);
+        result.push(self.status_mask);
+        result.push(self.rgb_mode as u8);
+        result.push(self.rgb_res as u8);
+
+        // Add expose_time as little endian
+        result.extend_from_slice(&self.expose_time.to_le_bytes());
+
+        result
+    }
 }
 
-pub fn frame_config_encode(
-    trigger_mode: u8,
-    deep_mode: u8,
-    deep_shift: u8,
-    ir_mode: u8,
-    status_mode: u8,
-    status_mask: u8,
-    rgb_mode: u8,
-    rgb_res: u8,
-    expose_time: i32,
-) -> Vec<u8> {
-    let mut result = Vec::with_capacity(12);
-    result.push(trigger_mode);
-    result.push(deep_mode);
-    result.push(deep_shift);
-    result.push(ir_mode);
-    result.push(status_mode);
-    result.push(status_mask);
-    result.push(rgb_mode);
-    result.push(rgb_res);
-
-    // Add expose_time as little endian
-    result.extend_from_slice(&expose_time.to_le_bytes());
-
-    result
+/// Builds a validated [`FrameConfig`], starting from the defaults
+#[derive(Debug, Clone, Default)]
+pub struct FrameConfigBuilder {
+    config: FrameConfig,
+}
+
+impl FrameConfigBuilder {
+    pub fn trigger_mode(mut self, trigger_mode: TriggerMode) -> Self {
+        self.config.trigger_mode = trigger_mode;
+        self
+    }
+
+    pub fn deep_mode(mut self, deep_mode: DeepMode) -> Self {
+        self.config.deep_mode = deep_mode;
+        self
+    }
+
+    /// Shift applied to depth in 8-bit mode, [`DEEP_SHIFT_AUTO`] for the firmware default
+    pub fn deep_shift(mut self, deep_shift: u8) -> Self {
+        self.config.deep_shift = deep_shift;
+        self
+    }
+
+    pub fn ir_mode(mut self, ir_mode: IrMode) -> Self {
+        self.config.ir_mode = ir_mode;
+        self
+    }
+
+    pub fn status_mode(mut self, status_mode: StatusMode) -> Self {
+        self.config.status_mode = status_mode;
+        self
+    }
+
+    pub fn status_mask(mut self, status_mask: u8) -> Self {
+        self.config.status_mask = status_mask;
+        self
+    }
+
+    pub fn rgb_mode(mut self, rgb_mode: RgbMode) -> Self {
+        self.config.rgb_mode = rgb_mode;
+        self
+    }
+
+    pub fn rgb_res(mut self, rgb_res: RgbRes) -> Self {
+        self.config.rgb_res = rgb_res;
+        self
+    }
+
+    /// Exposure time, 0 for automatic exposure
+    pub fn expose_time(mut self, expose_time: i32) -> Self {
+        self.config.expose_time = expose_time;
+        self
+    }
+
+    pub fn build(self) -> Result<FrameConfig, Box<dyn std::error::Error>> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+pub struct FramePayload {
+    resolution: Resolution,
+    depth_img: Option<Vec<u8>>,
+    ir_img: Option<Vec<u8>>,
+    status_img: Option<Vec<u8>>,
+    rgb_img: Option<Vec<u8>>,
 }
 
 fn frame_payload_decode(
@@ -110,8 +419,21 @@ fn frame_payload_decode(
 
     let mut payload = &frame_data[8..];
 
+    let resolution = Resolution::from_deep_size(deep_data_size as usize, config);
+    let (depth_size, ir_size, status_size) = resolution.image_sizes(config);
+
+    // Cut off mid image, e.g. by a dropped connection
+    let deep_size = depth_size + ir_size + status_size;
+    if payload.len() < deep_size {
+        return Err(format!(
+            "Frame truncated, {} of {} bytes of deep data",
+            payload.len(),
+            deep_size
+        )
+        .into());
+    }
+
     // Depth image
-    let depth_size = (320 * 240 * 2) >> config.deep_mode;
     let depth_img = if depth_size > 0 && payload.len() >= depth_size {
         let result = payload[..depth_size].to_vec();
         payload = &payload[depth_size..];
@@ -121,7 +443,6 @@ fn frame_payload_decode(
     };
 
     // IR image
-    let ir_size = (320 * 240 * 2) >> config.ir_mode;
     let ir_img = if ir_size > 0 && payload.len() >= ir_size {
         let result = payload[..ir_size].to_vec();
         payload = &payload[ir_size..];
@@ -131,14 +452,6 @@ fn frame_payload_decode(
     };
 
     // Status image
-    let status_size = (320 * 240 / 8)
-        * match config.status_mode {
-            0 => 16,
-            1 => 2,
-            2 => 8,
-            _ => 1,
-        };
-
     let status_img = if status_size > 0 && payload.len() >= status_size {
         let result = payload[..status_size].to_vec();
         payload = &payload[status_size..];
@@ -147,15 +460,6 @@ fn frame_payload_decode(
         None
     };
 
-    // Verify deep data size
-    let calculated_deep_size = depth_size + ir_size + status_size;
-    if calculated_deep_size != deep_data_size as usize {
-        warn!(
-            "Warning: Deep data size mismatch: {} vs {}",
-            calculated_deep_size, deep_data_size
-        );
-    }
-
     // RGB image
     let rgb_size = payload.len();
     if rgb_size != rgb_data_size as usize {
@@ -167,11 +471,9 @@ fn frame_payload_decode(
 
     let rgb_img = if rgb_size > 0 {
         // Process RGB image based on config
-        if config.rgb_mode == 1 {
+        if config.rgb_mode == RgbMode::Jpeg {
             // JPEG decode using OpenCV
-            let rgb_data = payload.to_vec();
-            let decoded = decode_jpeg(&rgb_data);
-            decoded
+            decode_jpeg(payload)
         } else {
             Some(payload.to_vec())
         }
@@ -180,6 +482,7 @@ fn frame_payload_decode(
     };
 
     Ok(FramePayload {
+        resolution,
         depth_img,
         ir_img,
         status_img,
@@ -196,24 +499,86 @@ fn decode_jpeg(jpeg_data: &[u8]) -> Option<Vec<u8>> {
     Some(rgb_img.into_raw())
 }
 
+/// Unpacks the status image into one value per pixel
+///
+/// Pixels are stored row by row. Sub-byte modes pack the first pixel into the
+/// least significant bits of each byte, and 16-bit values are little endian.
+/// Only the flags selected by `status_mask` are kept, so a pixel is valid
+/// exactly when its unpacked value is 0. The mask is a byte like on the
+/// camera, so in 16-bit mode the high byte is always cleared.
+fn unpack_status(
+    data: &[u8],
+    resolution: Resolution,
+    mode: StatusMode,
+    status_mask: u8,
+) -> Array2<u16> {
+    let bits = mode.bits();
+    let mask = u16::from(status_mask);
+
+    Array2::from_shape_fn(resolution.shape(), |(y, x)| {
+        let pixel = y * resolution.width + x;
+
+        let value = match bits {
+            16 => match data.get(pixel * 2..pixel * 2 + 2) {
+                Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+                None => 0,
+            },
+            8 => data.get(pixel).copied().map_or(0, u16::from),
+            _ => {
+                let bit = pixel * bits;
+                let pixel_mask = (1u8 << bits) - 1;
+                data.get(bit / 8)
+                    .map_or(0, |byte| u16::from((byte >> (bit % 8)) & pixel_mask))
+            }
+        };
+
+        value & mask
+    })
+}
+
+/// Reads the frame id and timestamp from the first 16 bytes of a frame
+pub fn decode_frame_header(frame_data: &[u8]) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+    if frame_data.len() < 16 {
+        return Err("Frame header too short".into());
+    }
+
+    let mut cursor = Cursor::new(&frame_data[0..16]);
+    let frame_id = cursor.read_u64::<LittleEndian>()?;
+    let stamp_msec = cursor.read_u64::<LittleEndian>()?;
+
+    Ok((frame_id, stamp_msec))
+}
+
+/// Reads the config the camera echoed after the frame header
+pub fn decode_frame_config(frame_data: &[u8]) -> Result<FrameConfig, Box<dyn std::error::Error>> {
+    match frame_data.get(16..28) {
+        Some(config) => FrameConfig::decode(config),
+        None => Err("Frame config too short".into()),
+    }
+}
+
 pub fn decode_frame(frame_data: &[u8]) -> Result<ProcessedFrames, Box<dyn std::error::Error>> {
     if frame_data.len() < 28 {
         // 16 (header) + 12 (config)
         return Err("Frame data too short".into());
     }
 
+    let (frame_id, stamp_msec) = decode_frame_header(frame_data)?;
+
     // Extract config
-    let config = frame_config_decode(&frame_data[16..28])?;
+    let config = decode_frame_config(frame_data)?;
 
     // Decode payload
     let payload = frame_payload_decode(&frame_data[28..], &config)?;
+    let resolution = payload.resolution;
 
-    // Process depth image
+    // Process depth image, converted to millimetres whatever the deep_mode
+    let units = DepthUnits::from_config(&config);
     let depth = if let Some(depth_data) = payload.depth_img {
-        if config.deep_mode == 0 {
+        if config.deep_mode == DeepMode::Bits16 {
             let data = depth_data.as_slice();
-            let depth_array = Array2::from_shape_fn((240, 320), |(y, x)| {
-                let idx = (y * 320 + x) * 2;
+            let depth_array = Array2::from_shape_fn(resolution.shape(), |(y, x)| {
+                let idx = (y * resolution.width + x) * 2;
                 if idx + 1 < data.len() {
                     u16::from_le_bytes([data[idx], data[idx + 1]])
                 } else {
@@ -223,10 +588,10 @@ pub fn decode_frame(frame_data: &[u8]) -> Result<ProcessedFrames, Box<dyn std::e
             Some(depth_array)
         } else {
             let data = depth_data.as_slice();
-            let depth_array = Array2::from_shape_fn((240, 320), |(y, x)| {
-                let idx = y * 320 + x;
+            let depth_array = Array2::from_shape_fn(resolution.shape(), |(y, x)| {
+                let idx = y * resolution.width + x;
                 if idx < data.len() {
-                    u16::from(data[idx])
+                    units.millimetres(u16::from(data[idx]))
                 } else {
                     0
                 }
@@ -239,10 +604,10 @@ pub fn decode_frame(frame_data: &[u8]) -> Result<ProcessedFrames, Box<dyn std::e
 
     // Process IR image
     let ir = if let Some(ir_data) = payload.ir_img {
-        if config.ir_mode == 0 {
+        if config.ir_mode == IrMode::Bits16 {
             let data = ir_data.as_slice();
-            let ir_array = Array2::from_shape_fn((240, 320), |(y, x)| {
-                let idx = (y * 320 + x) * 2;
+            let ir_array = Array2::from_shape_fn(resolution.shape(), |(y, x)| {
+                let idx = (y * resolution.width + x) * 2;
                 if idx + 1 < data.len() {
                     u16::from_le_bytes([data[idx], data[idx + 1]])
                 } else {
@@ -252,8 +617,8 @@ pub fn decode_frame(frame_data: &[u8]) -> Result<ProcessedFrames, Box<dyn std::e
             Some(ir_array)
         } else {
             let data = ir_data.as_slice();
-            let ir_array = Array2::from_shape_fn((240, 320), |(y, x)| {
-                let idx = y * 320 + x;
+            let ir_array = Array2::from_shape_fn(resolution.shape(), |(y, x)| {
+                let idx = y * resolution.width + x;
                 if idx < data.len() {
                     u16::from(data[idx])
                 } else {
@@ -267,30 +632,17 @@ pub fn decode_frame(frame_data: &[u8]) -> Result<ProcessedFrames, Box<dyn std::e
     };
 
     // Process status image
-    let status = if let Some(status_data) = payload.status_img {
-        // Process according to status_mode
-        let data = status_data.as_slice();
-        let status_array = Array2::from_shape_fn((240, 320), |(y, x)| {
-            // This is a simplified approach - actual processing depends on status_mode
-            let idx = y * 320 + x;
-            if idx < data.len() {
-                u16::from(data[idx])
-            } else {
-                0
-            }
+    let status = payload
+        .status_img
+        .map(|status_data| {
+            unpack_status(&status_data, resolution, config.status_mode, config.status_mask)
         });
-        Some(status_array)
-    } else {
-        None
-    };
 
     // Process RGB image
     let rgb = if let Some(rgb_data) = payload.rgb_img {
-        let shape = if config.rgb_mode == 1 {
-            match config.rgb_res {
-                0 => (480, 640, 3), // Default resolution
-                _ => (600, 800, 3), // Alternative resolution
-            }
+        let shape = if config.rgb_mode == RgbMode::Jpeg {
+            let (width, height) = config.rgb_res.dimensions();
+            (height, width, 3)
         } else {
             (480, 640, 3) // Default for non-JPEG
         };
@@ -311,6 +663,9 @@ pub fn decode_frame(frame_data: &[u8]) -> Result<ProcessedFrames, Box<dyn std::e
     };
 
     Ok(ProcessedFrames {
+        frame_id,
+        stamp_msec,
+        resolution,
         depth,
         ir,
         status,
@@ -318,18 +673,142 @@ pub fn decode_frame(frame_data: &[u8]) -> Result<ProcessedFrames, Box<dyn std::e
     })
 }
 
-pub fn normalize(data: &Array2<u16>) -> Vec<u8> {
-    let mut result = Vec::with_capacity(data.dim().0 * data.dim().1 * 3);
+/// Builds a raw `/getdeep` body from images, the inverse of [`decode_frame`]
+///
+/// Missing images are sent as zeros so the payload always matches `config`.
+pub fn encode_frame(
+    frame_id: u64,
+    stamp_msec: u64,
+    config: &FrameConfig,
+    frames: &ProcessedFrames,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let resolution = frames.resolution;
+    let pixels = resolution.pixels();
+    let pixel = |i: usize| (i / resolution.width, i % resolution.width);
 
-    let max = 255. / (*data.iter().max().unwrap_or(&255u16) as f32);
+    // Depth image
+    let mut deep_data = Vec::new();
+    let depth = frames.depth.as_ref();
+    let units = DepthUnits::from_config(config);
+    for i in 0..pixels {
+        let value = units.raw(depth.map_or(0, |depth| depth[pixel(i)]));
+        match config.deep_mode {
+            DeepMode::Bits16 => deep_data.extend_from_slice(&value.to_le_bytes()),
+            DeepMode::Bits8 => deep_data.push(value as u8),
+        }
+    }
 
-    for &value in data.iter() {
-        let num = (value as f32 * max) as u8;
+    // IR image
+    let ir = frames.ir.as_ref();
+    for i in 0..pixels {
+        let value = ir.map_or(0, |ir| ir[pixel(i)]);
+        match config.ir_mode {
+            IrMode::Bits16 => deep_data.extend_from_slice(&value.to_le_bytes()),
+            IrMode::Bits8 => deep_data.push(value.min(255) as u8),
+        }
+    }
 
-        result.push(num);
-        result.push(num);
-        result.push(num);
+    // Status image, packed least significant bits first
+    let status = frames.status.as_ref();
+    let bits = config.status_mode.bits();
+    let mut status_data = vec![0u8; (pixels * bits).div_ceil(8)];
+    for i in 0..pixels {
+        let value = status.map_or(0, |status| status[pixel(i)]);
+        match bits {
+            16 => status_data[i * 2..i * 2 + 2].copy_from_slice(&value.to_le_bytes()),
+            8 => status_data[i] = value as u8,
+            _ => {
+                let bit = i * bits;
+                let mask = (1u16 << bits) - 1;
+                status_data[bit / 8] |= ((value & mask) as u8) << (bit % 8);
+            }
+        }
+    }
+    deep_data.append(&mut status_data);
+
+    // RGB image
+    let mut rgb_data = Vec::new();
+    if let Some(ref rgb) = frames.rgb {
+        let (height, width, _) = rgb.dim();
+        let raw: Vec<u8> = rgb.iter().copied().collect();
+
+        if config.rgb_mode == RgbMode::Jpeg {
+            image::codecs::jpeg::JpegEncoder::new(&mut rgb_data).encode(
+                &raw,
+                width as u32,
+                height as u32,
+                image::ExtendedColorType::Rgb8,
+            )?;
+        } else {
+            rgb_data = raw;
+        }
     }
 
-    result
+    let mut result = Vec::with_capacity(36 + deep_data.len() + rgb_data.len());
+    result.extend_from_slice(&frame_id.to_le_bytes());
+    result.extend_from_slice(&stamp_msec.to_le_bytes());
+    result.append(&mut config.encode());
+    result.extend_from_slice(&(deep_data.len() as i32).to_le_bytes());
+    result.extend_from_slice(&(rgb_data.len() as i32).to_le_bytes());
+    result.append(&mut deep_data);
+    result.append(&mut rgb_data);
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_is_what_the_proxy_always_sent() {
+        assert_eq!(FrameConfig::default().encode(), [1, 0, 255, 0, 2, 7, 1, 0, 0, 0, 0, 0]);
+    }
+
+    /// Status of a frame sent with `mode` and `status_mask` and decoded again
+    fn status_round_trip(mode: StatusMode, status_mask: u8, status: &Array2<u16>) -> Array2<u16> {
+        let config = FrameConfig::builder()
+            .status_mode(mode)
+            .status_mask(status_mask)
+            .build()
+            .unwrap();
+        let frames = ProcessedFrames {
+            resolution: Resolution::A075,
+            status: Some(status.clone()),
+            ..ProcessedFrames::default()
+        };
+
+        let frame = encode_frame(1, 2, &config, &frames).unwrap();
+        decode_frame(&frame).unwrap().status.unwrap()
+    }
+
+    #[test]
+    fn status_round_trips_in_every_mode() {
+        for mode in [StatusMode::Bits16, StatusMode::Bits2, StatusMode::Bits8, StatusMode::Bits1] {
+            let largest = (1 << mode.bits().min(8)) - 1;
+            let status = Array2::from_shape_fn(Resolution::A075.shape(), |(y, x)| {
+                ((y * 7 + x * 3) % (largest + 1)) as u16
+            });
+
+            assert_eq!(status_round_trip(mode, 0xff, &status), status, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn status_mask_keeps_selected_flags() {
+        let status = Array2::from_shape_fn(Resolution::A075.shape(), |(_, x)| (x % 8) as u16);
+
+        let masked = status_round_trip(StatusMode::Bits8, 0b101, &status);
+
+        assert_eq!(masked, status.mapv(|value| value & 0b101));
+    }
+
+    #[test]
+    fn status_mask_clears_high_byte_of_16_bit_status() {
+        let status = Array2::from_elem(Resolution::A075.shape(), 0x0103);
+
+        let masked = status_round_trip(StatusMode::Bits16, 0xff, &status);
+
+        assert!(masked.iter().all(|&value| value == 0x03));
+    }
 }
@@ -0,0 +1,41 @@
+use ndarray::{Array2, Zip};
+
+/// Settings of the confidence threshold
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfidenceConfig {
+    /// IR amplitude below which a pixel is dropped
+    ///
+    /// In the units the camera sends, so 8-bit IR needs a lower threshold
+    /// than 16-bit IR.
+    pub min_amplitude: u16,
+    /// Give pixels flagged in the status image zero confidence
+    pub use_status: bool,
+}
+
+impl Default for ConfidenceConfig {
+    fn default() -> Self {
+        Self {
+            min_amplitude: 16,
+            use_status: true,
+        }
+    }
+}
+
+/// Per-pixel confidence in the depth reading
+///
+/// The depth of a pixel is only as good as the light that came back to it,
+/// so dark and far surfaces with a weak IR return are the least trustworthy.
+/// Confidence is the IR amplitude, or 0 where the status image flags the
+/// pixel and `use_status` is set.
+pub fn confidence(
+    ir: &Array2<u16>,
+    status: Option<&Array2<u16>>,
+    config: &ConfidenceConfig,
+) -> Array2<u16> {
+    match status {
+        Some(status) if config.use_status && status.dim() == ir.dim() => Zip::from(ir)
+            .and(status)
+            .map_collect(|&amplitude, &s| if s == 0 { amplitude } else { 0 }),
+        _ => ir.clone(),
+    }
+}
@@ -0,0 +1,128 @@
+use ndarray::Array2;
+
+/// How large a depth jump between neighbours counts as a discontinuity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Discontinuity {
+    /// Jump relative to the nearer of the two depths
+    Ratio(f32),
+    /// Angle in degrees between the surface joining two neighbours and the
+    /// image plane
+    Angle(f32),
+}
+
+/// Settings of the flying pixel filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlyingPixelConfig {
+    pub threshold: Discontinuity,
+}
+
+impl Default for FlyingPixelConfig {
+    fn default() -> Self {
+        Self {
+            threshold: Discontinuity::Angle(80.),
+        }
+    }
+}
+
+impl FlyingPixelConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.threshold {
+            Discontinuity::Ratio(ratio) if ratio.is_nan() || ratio <= 0. => {
+                Err(format!("Flying pixel ratio must be positive, got {}", ratio).into())
+            }
+            Discontinuity::Angle(angle) if !(angle > 0. && angle < 90.) => {
+                Err(format!("Flying pixel angle must be in (0, 90) degrees, got {}", angle).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Largest jump between neighbours, as a fraction of the nearer depth
+    ///
+    /// Neighbouring pixels at depth `d` are about `d / focal_px` apart, so a
+    /// surface at `angle` to the image plane changes depth by
+    /// `d * tan(angle) / focal_px` from one pixel to the next.
+    fn max_jump(&self, focal_px: f32) -> f32 {
+        match self.threshold {
+            Discontinuity::Ratio(ratio) => ratio,
+            Discontinuity::Angle(angle) => angle.to_radians().tan() / focal_px,
+        }
+    }
+}
+
+/// Removes pixels that hang between a foreground edge and the background
+///
+/// The ToF sensor mixes the returns of both surfaces at an edge, so those
+/// pixels end up somewhere in between. A pixel is dropped when it jumps away
+/// from the neighbours on both sides, horizontally or vertically. Pixels on
+/// the edge of a real surface only jump on one side and are kept.
+///
+/// Returns the filtered depth and how many pixels were dropped.
+pub fn remove_flying_pixels(
+    depth: &Array2<u16>,
+    config: &FlyingPixelConfig,
+    focal_px: f32,
+) -> (Array2<u16>, usize) {
+    let max_jump = config.max_jump(focal_px);
+    let (height, width) = depth.dim();
+    let mut dropped = 0;
+
+    let jumps = |d: u16, y: usize, x: usize| {
+        let neighbour = depth[(y, x)];
+        neighbour != 0 && f32::from(d.abs_diff(neighbour)) > max_jump * f32::from(d.min(neighbour))
+    };
+
+    let filtered = Array2::from_shape_fn(depth.dim(), |(y, x)| {
+        let d = depth[(y, x)];
+        if d == 0 {
+            return 0;
+        }
+
+        let horizontal = x > 0 && x + 1 < width && jumps(d, y, x - 1) && jumps(d, y, x + 1);
+        let vertical = y > 0 && y + 1 < height && jumps(d, y - 1, x) && jumps(d, y + 1, x);
+
+        if horizontal || vertical {
+            dropped += 1;
+            0
+        } else {
+            d
+        }
+    });
+
+    (filtered, dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Roughly the A075's focal length in pixels
+    const FOCAL_PX: f32 = 230.;
+
+    #[test]
+    fn pixel_between_edge_and_background_is_dropped() {
+        let depth = Array2::from_shape_vec((1, 5), vec![1000, 1000, 1500, 2000, 2000]).unwrap();
+
+        let (filtered, dropped) =
+            remove_flying_pixels(&depth, &FlyingPixelConfig::default(), FOCAL_PX);
+
+        assert_eq!(dropped, 1);
+        assert_eq!(filtered.as_slice().unwrap(), [1000, 1000, 0, 2000, 2000]);
+    }
+
+    #[test]
+    fn flat_surfaces_are_kept() {
+        // Facing the camera, and turned 60 degrees away from it
+        let facing = Array2::from_elem((5, 5), 1000);
+        let step = 60f32.to_radians().tan() / FOCAL_PX * 1000.;
+        let turned = Array2::from_shape_fn((5, 5), |(_, x)| (1000. + step * x as f32) as u16);
+
+        for depth in [facing, turned] {
+            let (filtered, dropped) =
+                remove_flying_pixels(&depth, &FlyingPixelConfig::default(), FOCAL_PX);
+
+            assert_eq!(dropped, 0);
+            assert_eq!(filtered, depth);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Per-pixel clean-up of depth images before they are projected
+//!
+//! Filters work on depth in millimetres, where 0 marks an invalid pixel like
+//! everywhere else depth is handled. The temporal filter also takes a
+//! validity mask, so which pixels count as valid is decided by the caller,
+//! usually from `confidence`. Hole filling takes the pixels that mask
+//! rejected, so it does not fill them back in.
+
+mod confidence;
+mod flying;
+mod spatial;
+mod temporal;
+
+use ndarray::Array2;
+
+pub use confidence::{confidence, ConfidenceConfig};
+pub use flying::{remove_flying_pixels, Discontinuity, FlyingPixelConfig};
+pub use spatial::{BilateralConfig, HoleFillConfig, MedianConfig};
+pub use temporal::{TemporalConfig, TemporalFilter};
+
+/// Which filters run and how, None disables a filter
+///
+/// They run in the order of the fields.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterConfig {
+    pub confidence: Option<ConfidenceConfig>,
+    pub temporal: Option<TemporalConfig>,
+    pub median: Option<MedianConfig>,
+    pub bilateral: Option<BilateralConfig>,
+    pub fill_holes: Option<HoleFillConfig>,
+    pub flying_pixels: Option<FlyingPixelConfig>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            confidence: Some(ConfidenceConfig::default()),
+            temporal: Some(TemporalConfig::default()),
+            median: Some(MedianConfig::default()),
+            bilateral: Some(BilateralConfig::default()),
+            fill_holes: Some(HoleFillConfig::default()),
+            flying_pixels: Some(FlyingPixelConfig::default()),
+        }
+    }
+}
+
+impl FilterConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(ref temporal) = self.temporal {
+            temporal.validate()?;
+        }
+        if let Some(ref median) = self.median {
+            median.validate()?;
+        }
+        if let Some(ref bilateral) = self.bilateral {
+            bilateral.validate()?;
+        }
+        if let Some(ref fill_holes) = self.fill_holes {
+            fill_holes.validate()?;
+        }
+        if let Some(ref flying_pixels) = self.flying_pixels {
+            flying_pixels.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the spatial filters that are turned on
+///
+/// `rejected` marks pixels that had depth but failed validation, they stay
+/// invalid.
+pub fn spatial_filter(
+    config: &FilterConfig,
+    mut depth: Array2<u16>,
+    rejected: &Array2<bool>,
+) -> Array2<u16> {
+    if let Some(ref median) = config.median {
+        depth = spatial::median(&depth, median);
+    }
+    if let Some(ref bilateral) = config.bilateral {
+        depth = spatial::bilateral(&depth, bilateral);
+    }
+    if let Some(ref fill_holes) = config.fill_holes {
+        depth = spatial::fill_holes(&depth, rejected, fill_holes);
+    }
+
+    depth
+}
@@ -0,0 +1,222 @@
+use ndarray::Array2;
+
+/// Settings of the median filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MedianConfig {
+    /// Window is `2 * radius + 1` pixels square
+    pub radius: usize,
+}
+
+impl Default for MedianConfig {
+    fn default() -> Self {
+        Self { radius: 1 }
+    }
+}
+
+/// Settings of the bilateral filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BilateralConfig {
+    /// Window is `2 * radius + 1` pixels square
+    pub radius: usize,
+    /// Falloff of the weights with distance, in pixels
+    pub sigma_space: f32,
+    /// Falloff of the weights with depth difference, in millimetres
+    pub sigma_depth_mm: f32,
+}
+
+impl Default for BilateralConfig {
+    fn default() -> Self {
+        Self {
+            radius: 2,
+            sigma_space: 1.5,
+            sigma_depth_mm: 30.,
+        }
+    }
+}
+
+/// Settings of the hole filling
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HoleFillConfig {
+    /// Window is `2 * radius + 1` pixels square
+    pub radius: usize,
+    /// Valid pixels the window needs before a hole in it is filled
+    pub min_neighbours: usize,
+}
+
+impl Default for HoleFillConfig {
+    fn default() -> Self {
+        Self {
+            radius: 1,
+            min_neighbours: 5,
+        }
+    }
+}
+
+impl MedianConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.radius == 0 {
+            return Err("Median radius must be at least 1".into());
+        }
+
+        Ok(())
+    }
+}
+
+impl BilateralConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.radius == 0 {
+            return Err("Bilateral radius must be at least 1".into());
+        }
+
+        if self.sigma_space.is_nan() || self.sigma_space <= 0. {
+            return Err(format!("Bilateral space sigma must be positive, got {}", self.sigma_space).into());
+        }
+
+        if self.sigma_depth_mm.is_nan() || self.sigma_depth_mm <= 0. {
+            return Err(format!(
+                "Bilateral depth sigma must be positive, got {} mm",
+                self.sigma_depth_mm
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+impl HoleFillConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.radius == 0 {
+            return Err("Hole filling radius must be at least 1".into());
+        }
+
+        let window = (2 * self.radius + 1).pow(2) - 1;
+        if self.min_neighbours == 0 || self.min_neighbours > window {
+            return Err(format!(
+                "Hole filling needs between 1 and {} neighbours, got {}",
+                window, self.min_neighbours
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Replaces every valid pixel with the median of the valid pixels around it
+///
+/// Removes speckle without blurring edges as much as an average would.
+pub fn median(depth: &Array2<u16>, config: &MedianConfig) -> Array2<u16> {
+    let mut window = Vec::with_capacity((2 * config.radius + 1).pow(2));
+
+    Array2::from_shape_fn(depth.dim(), |(y, x)| {
+        if depth[(y, x)] == 0 {
+            return 0;
+        }
+
+        window.clear();
+        window.extend(neighbourhood(depth, y, x, config.radius).map(|(_, _, d)| d));
+
+        let middle = window.len() / 2;
+        *window.select_nth_unstable(middle).1
+    })
+}
+
+/// Averages valid pixels weighted by both distance and depth difference
+///
+/// Pixels on the other side of a depth edge get almost no weight, so
+/// surfaces are smoothed while their outlines stay sharp.
+pub fn bilateral(depth: &Array2<u16>, config: &BilateralConfig) -> Array2<u16> {
+    let radius = config.radius as isize;
+    let size = 2 * config.radius + 1;
+
+    let space_weights = Array2::from_shape_fn((size, size), |(dy, dx)| {
+        let dy = (dy as isize - radius) as f32;
+        let dx = (dx as isize - radius) as f32;
+        (-(dx * dx + dy * dy) / (2. * config.sigma_space.powi(2))).exp()
+    });
+    let depth_falloff = 2. * config.sigma_depth_mm.powi(2);
+
+    Array2::from_shape_fn(depth.dim(), |(y, x)| {
+        let centre = depth[(y, x)];
+        if centre == 0 {
+            return 0;
+        }
+
+        let mut sum = 0.;
+        let mut total_weight = 0.;
+
+        for (ny, nx, d) in neighbourhood(depth, y, x, config.radius) {
+            let difference = f32::from(d) - f32::from(centre);
+            let weight = space_weights[(ny + config.radius - y, nx + config.radius - x)]
+                * (-(difference * difference) / depth_falloff).exp();
+
+            sum += weight * f32::from(d);
+            total_weight += weight;
+        }
+
+        (sum / total_weight).round() as u16
+    })
+}
+
+/// Fills invalid pixels surrounded by enough valid ones, except `rejected` ones
+///
+/// Holes take the median of their valid neighbours, so a hole on a depth edge
+/// joins one side instead of floating between the two.
+pub fn fill_holes(
+    depth: &Array2<u16>,
+    rejected: &Array2<bool>,
+    config: &HoleFillConfig,
+) -> Array2<u16> {
+    let mut window = Vec::with_capacity((2 * config.radius + 1).pow(2));
+
+    Array2::from_shape_fn(depth.dim(), |(y, x)| {
+        if depth[(y, x)] != 0 || rejected[(y, x)] {
+            return depth[(y, x)];
+        }
+
+        window.clear();
+        window.extend(neighbourhood(depth, y, x, config.radius).map(|(_, _, d)| d));
+
+        if window.len() < config.min_neighbours {
+            return 0;
+        }
+
+        let middle = window.len() / 2;
+        *window.select_nth_unstable(middle).1
+    })
+}
+
+/// Valid pixels in the window around `(y, x)`, clipped to the image
+fn neighbourhood(
+    depth: &Array2<u16>,
+    y: usize,
+    x: usize,
+    radius: usize,
+) -> impl Iterator<Item = (usize, usize, u16)> + '_ {
+    let (height, width) = depth.dim();
+    let rows = y.saturating_sub(radius)..(y + radius + 1).min(height);
+    let columns = x.saturating_sub(radius)..(x + radius + 1).min(width);
+
+    rows.flat_map(move |ny| columns.clone().map(move |nx| (ny, nx, depth[(ny, nx)])))
+        .filter(|&(_, _, d)| d != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_holes_skips_rejected_pixels() {
+        let mut depth = Array2::from_elem((3, 5), 1000);
+        depth[(1, 1)] = 0;
+        depth[(1, 3)] = 0;
+        let mut rejected = Array2::from_elem(depth.dim(), false);
+        rejected[(1, 3)] = true;
+
+        let filled = fill_holes(&depth, &rejected, &HoleFillConfig::default());
+
+        assert_eq!(filled[(1, 1)], 1000);
+        assert_eq!(filled[(1, 3)], 0);
+    }
+}
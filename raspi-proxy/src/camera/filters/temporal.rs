@@ -0,0 +1,118 @@
+use ndarray::{Array2, Zip};
+
+/// Settings of the temporal filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemporalConfig {
+    /// Weight of the newest frame, 1 turns the filter off
+    pub alpha: f32,
+    /// Change in millimetres above which a pixel drops its history
+    pub reset_mm: f32,
+}
+
+impl Default for TemporalConfig {
+    fn default() -> Self {
+        Self {
+            alpha: 0.5,
+            reset_mm: 100.,
+        }
+    }
+}
+
+impl TemporalConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if !(self.alpha > 0. && self.alpha <= 1.) {
+            return Err(format!("Temporal alpha must be in (0, 1], got {}", self.alpha).into());
+        }
+
+        if self.reset_mm.is_nan() || self.reset_mm <= 0. {
+            return Err(format!("Temporal reset must be positive, got {} mm", self.reset_mm).into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Exponential moving average of depth over frames
+///
+/// Each pixel averages only over the frames it was valid in. A pixel that
+/// turns invalid forgets its history, and one whose depth jumps by more than
+/// `reset_mm` starts over from the new value, so moving edges do not smear.
+#[derive(Default)]
+pub struct TemporalFilter {
+    /// Filtered depth in millimetres, NaN where there is no history
+    average: Array2<f32>,
+}
+
+impl TemporalFilter {
+    /// Adds a frame and returns the filtered depth in millimetres
+    pub fn apply(
+        &mut self,
+        config: &TemporalConfig,
+        depth: &Array2<u16>,
+        valid: &Array2<bool>,
+    ) -> Array2<u16> {
+        if self.average.dim() != depth.dim() {
+            self.average = Array2::from_elem(depth.dim(), f32::NAN);
+        }
+
+        Zip::from(&mut self.average)
+            .and(depth)
+            .and(valid)
+            .map_collect(|average, &depth, &valid| {
+                if !valid || depth == 0 {
+                    *average = f32::NAN;
+                    return 0;
+                }
+
+                let depth = f32::from(depth);
+                if average.is_nan() || (depth - *average).abs() > config.reset_mm {
+                    *average = depth;
+                } else {
+                    *average += config.alpha * (depth - *average);
+                }
+
+                average.round() as u16
+            })
+    }
+
+    /// Forgets every frame seen so far
+    pub fn reset(&mut self) {
+        self.average.fill(f32::NAN);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs one pixel through the filter, None marks an invalid frame
+    fn filter(frames: &[Option<u16>]) -> Vec<u16> {
+        let mut filter = TemporalFilter::default();
+        let config = TemporalConfig::default();
+
+        frames
+            .iter()
+            .map(|frame| {
+                let depth = Array2::from_elem((1, 1), frame.unwrap_or(1000));
+                let valid = Array2::from_elem((1, 1), frame.is_some());
+                filter.apply(&config, &depth, &valid)[(0, 0)]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn small_changes_are_averaged() {
+        assert_eq!(filter(&[Some(1000), Some(1020), Some(1020)]), [1000, 1010, 1015]);
+    }
+
+    #[test]
+    fn motion_resets_the_average() {
+        assert_eq!(filter(&[Some(1000), Some(1500), Some(1520)]), [1000, 1500, 1510]);
+    }
+
+    #[test]
+    fn invalid_pixels_drop_their_history() {
+        assert_eq!(filter(&[Some(1000), None, Some(1040)]), [1000, 0, 1040]);
+        assert_eq!(filter(&[Some(1000), Some(0), Some(1040)]), [1000, 0, 1040]);
+    }
+}
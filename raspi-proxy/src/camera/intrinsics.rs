@@ -1,4 +1,4 @@
-use std::f64;
+use ndarray::Array2;
 
 pub static DEFAULT_INTRINSICS: CameraIntrinsics = CameraIntrinsics {
     fx: 2.318290e+02,
@@ -12,8 +12,11 @@ pub static DEFAULT_INTRINSICS: CameraIntrinsics = CameraIntrinsics {
     p2: 1.658998e-03,
 };
 
+/// Depth image size `DEFAULT_INTRINSICS` were calibrated at, as (height, width)
+pub const DEFAULT_INTRINSICS_SHAPE: (usize, usize) = (240, 320);
+
 /// Camera intrinsic parameters
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct CameraIntrinsics {
     pub fx: f64, // focal length x
     pub fy: f64, // focal length y
@@ -27,6 +30,7 @@ pub struct CameraIntrinsics {
 }
 
 impl CameraIntrinsics {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         fx: f64,
         fy: f64,
@@ -50,6 +54,79 @@ impl CameraIntrinsics {
             p2,
         }
     }
+
+    /// The same lens at another image size, e.g. with the sensor binned
+    ///
+    /// Distortion works on normalized coordinates so only the focal lengths
+    /// and principal point change. Shapes are (height, width).
+    pub fn scaled(&self, from: (usize, usize), to: (usize, usize)) -> Self {
+        let scale_x = to.1 as f64 / from.1 as f64;
+        let scale_y = to.0 as f64 / from.0 as f64;
+
+        Self {
+            fx: self.fx * scale_x,
+            fy: self.fy * scale_y,
+            // Scale about pixel corners rather than pixel centres
+            u0: (self.u0 + 0.5) * scale_x - 0.5,
+            v0: (self.v0 + 0.5) * scale_y - 0.5,
+            ..*self
+        }
+    }
+}
+
+/// Newton iterations of the inverse distortion model, it converges in a few
+const UNDISTORT_ITERATIONS: usize = 20;
+
+/// Largest error, in normalized coordinates, of an accepted inverse
+const UNDISTORT_TOLERANCE: f64 = 1e-9;
+
+/// Precomputed viewing ray of every pixel
+///
+/// Each ray is scaled so its z is 1, because the camera reports depth along
+/// the optical axis rather than distance from the lens. Building the table
+/// runs the inverse distortion once per pixel, after which projecting a pixel
+/// is a multiply.
+pub struct RayTable {
+    /// Normalized (x, y) image coordinates of each undistorted pixel, None
+    /// where the distortion model has no inverse
+    rays: Array2<Option<(f32, f32)>>,
+}
+
+impl RayTable {
+    /// Builds the rays for an image of `(height, width)` pixels
+    pub fn new(intrinsics: &CameraIntrinsics, (height, width): (usize, usize)) -> Self {
+        let rays = Array2::from_shape_fn((height, width), |(y, x)| {
+            undistort(x as f64, y as f64, intrinsics)
+                .map(|(x_norm, y_norm)| (x_norm as f32, y_norm as f32))
+        });
+
+        let table = Self { rays };
+        let missing = table.missing();
+        if missing > 0 {
+            warn!(
+                "Lens model cannot undistort {} of {} pixels, they are skipped",
+                missing,
+                table.rays.len()
+            );
+        }
+
+        table
+    }
+
+    /// Pixels without a ray
+    pub fn missing(&self) -> usize {
+        self.rays.iter().filter(|ray| ray.is_none()).count()
+    }
+
+    /// Size of the image the table covers, as `(height, width)`
+    pub fn dim(&self) -> (usize, usize) {
+        self.rays.dim()
+    }
+
+    /// Normalized (x, y) of the pixel at column `x` and row `y`
+    pub fn ray(&self, x: usize, y: usize) -> Option<(f32, f32)> {
+        *self.rays.get((y, x))?
+    }
 }
 
 /// Convert a pixel with depth information to a 3D point
@@ -57,72 +134,161 @@ impl CameraIntrinsics {
 /// # Arguments
 /// * `x` - x-coordinate in the image (columns)
 /// * `y` - y-coordinate in the image (rows)
-/// * `depth` - depth value at the pixel (typically in millimeters)
-/// * `intrinsics` - camera intrinsic parameters
+/// * `depth` - depth value at the pixel in millimetres
+/// * `rays` - rays of the camera the image came from
 ///
 /// # Returns
-/// A 3D point in the camera coordinate system
+/// A 3D point in the camera coordinate system in millimetres, or None for
+/// pixels outside the table or without a ray
 pub fn depth_to_point_cloud(
-    x: i32,
-    y: i32,
+    x: usize,
+    y: usize,
     depth: u16,
-    intrinsics: &CameraIntrinsics,
-) -> (i32, i32, i32) {
-    // Convert pixel coordinates to normalized image coordinates
-    let x_f = x as f64;
-    let y_f = y as f64;
-
-    // Apply distortion correction if needed
-    let (x_corrected, y_corrected) = correct_distortion(x_f, y_f, intrinsics);
-
-    // Convert normalized image coordinates to camera coordinates
-    let z = depth as f64; // depth in camera z-direction
-
-    // Back-project using the pinhole camera model
-    // (x - u0) / fx = X / Z
-    // (y - v0) / fy = Y / Z
-    let x_3d = (x_corrected - intrinsics.u0) * z / intrinsics.fx;
-    let y_3d = (y_corrected - intrinsics.v0) * z / intrinsics.fy;
-
-    // Return the 3D point, converting to integer values if needed
-    (x_3d.round() as i32, y_3d.round() as i32, z.round() as i32)
+    rays: &RayTable,
+) -> Option<(f32, f32, f32)> {
+    let (x_norm, y_norm) = (*rays.rays.get((y, x))?)?;
+    let z = f32::from(depth);
+
+    Some((x_norm * z, y_norm * z, z))
 }
 
-/// Corrects for lens distortion based on the Brown-Conrady model
+/// Project a 3D point into the image, the inverse of `depth_to_point_cloud`
 ///
 /// # Arguments
-/// * `x` - uncorrected x coordinate in pixel space
-/// * `y` - uncorrected y coordinate in pixel space
-/// * `intrinsics` - camera intrinsic parameters with distortion coefficients
+/// * `point` - point in the camera coordinate system
+/// * `intrinsics` - intrinsics of the camera to project into
 ///
 /// # Returns
-/// Corrected (x, y) coordinates
-fn correct_distortion(x: f64, y: f64, intrinsics: &CameraIntrinsics) -> (f64, f64) {
-    // Convert to normalized image coordinates (centered at principal point)
-    let x_norm = (x - intrinsics.u0) / intrinsics.fx;
-    let y_norm = (y - intrinsics.v0) / intrinsics.fy;
+/// Pixel coordinates (column, row), or None behind the camera
+pub fn point_to_pixel(point: (f64, f64, f64), intrinsics: &CameraIntrinsics) -> Option<(f64, f64)> {
+    let (x, y, z) = point;
+    if z <= 0.0 {
+        return None;
+    }
+
+    let ((x_distorted, y_distorted), _) = distort(x / z, y / z, intrinsics);
+
+    Some((
+        x_distorted * intrinsics.fx + intrinsics.u0,
+        y_distorted * intrinsics.fy + intrinsics.v0,
+    ))
+}
+
+/// Applies the Brown-Conrady model to undistorted normalized coordinates
+///
+/// Returns the distorted coordinates and their Jacobian
+/// `[[dx/dx, dx/dy], [dy/dx, dy/dy]]`.
+fn distort(x: f64, y: f64, intrinsics: &CameraIntrinsics) -> ((f64, f64), [[f64; 2]; 2]) {
+    let CameraIntrinsics { k1, k2, k3, p1, p2, .. } = *intrinsics;
 
     // Calculate squared radius for radial distortion
-    let r2 = x_norm * x_norm + y_norm * y_norm;
+    let r2 = x * x + y * y;
     let r4 = r2 * r2;
     let r6 = r4 * r2;
 
-    // Calculate radial distortion factor
-    let radial_factor = 1.0 + intrinsics.k1 * r2 + intrinsics.k2 * r4 + intrinsics.k3 * r6;
+    // Radial distortion factor and its derivative by r2
+    let radial = 1.0 + k1 * r2 + k2 * r4 + k3 * r6;
+    let radial_slope = k1 + 2.0 * k2 * r2 + 3.0 * k3 * r4;
 
-    // Calculate tangential distortion
-    let tangential_x =
-        2.0 * intrinsics.p1 * x_norm * y_norm + intrinsics.p2 * (r2 + 2.0 * x_norm * x_norm);
-    let tangential_y =
-        intrinsics.p1 * (r2 + 2.0 * y_norm * y_norm) + 2.0 * intrinsics.p2 * x_norm * y_norm;
+    let distorted = (
+        x * radial + 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x),
+        y * radial + p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y,
+    );
 
-    // Apply distortion correction
-    let x_corrected = x_norm * radial_factor + tangential_x;
-    let y_corrected = y_norm * radial_factor + tangential_y;
+    let cross = 2.0 * x * y * radial_slope + 2.0 * p1 * x + 2.0 * p2 * y;
+    let jacobian = [
+        [radial + 2.0 * x * x * radial_slope + 2.0 * p1 * y + 6.0 * p2 * x, cross],
+        [cross, radial + 2.0 * y * y * radial_slope + 6.0 * p1 * y + 2.0 * p2 * x],
+    ];
 
-    // Convert back to pixel coordinates
-    let x_pixel = x_corrected * intrinsics.fx + intrinsics.u0;
-    let y_pixel = y_corrected * intrinsics.fy + intrinsics.v0;
+    (distorted, jacobian)
+}
 
-    (x_pixel, y_pixel)
+/// Removes lens distortion based on the Brown-Conrady model
+///
+/// The model maps undistorted to distorted coordinates and has no closed
+/// form inverse, so it is inverted with Newton's method starting from the
+/// distorted point. Far from the centre the model can fold back on itself,
+/// and pixels past the fold have no inverse.
+///
+/// # Arguments
+/// * `x` - distorted x coordinate in pixel space
+/// * `y` - distorted y coordinate in pixel space
+/// * `intrinsics` - camera intrinsic parameters with distortion coefficients
+///
+/// # Returns
+/// Undistorted normalized image coordinates, i.e. X / Z and Y / Z
+fn undistort(x: f64, y: f64, intrinsics: &CameraIntrinsics) -> Option<(f64, f64)> {
+    // Convert to normalized image coordinates (centered at principal point)
+    let target = ((x - intrinsics.u0) / intrinsics.fx, (y - intrinsics.v0) / intrinsics.fy);
+
+    let (mut x_norm, mut y_norm) = target;
+
+    for _ in 0..UNDISTORT_ITERATIONS {
+        let ((x_distorted, y_distorted), [[a, b], [c, d]]) = distort(x_norm, y_norm, intrinsics);
+        let (error_x, error_y) = (x_distorted - target.0, y_distorted - target.1);
+
+        if error_x.abs() + error_y.abs() < UNDISTORT_TOLERANCE {
+            // Only accept solutions on the unfolded side of the model
+            return (a * d - b * c > 0.0).then_some((x_norm, y_norm));
+        }
+
+        let determinant = a * d - b * c;
+        if determinant <= 0.0 {
+            return None;
+        }
+
+        x_norm -= (d * error_x - b * error_y) / determinant;
+        y_norm -= (a * error_y - c * error_x) / determinant;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undistort_inverts_distort() {
+        let (height, width) = DEFAULT_INTRINSICS_SHAPE;
+        let i = &DEFAULT_INTRINSICS;
+
+        for y in 0..height {
+            for x in 0..width {
+                let Some((x_norm, y_norm)) = undistort(x as f64, y as f64, i) else {
+                    continue;
+                };
+                let ((x_distorted, y_distorted), _) = distort(x_norm, y_norm, i);
+                let pixel = (x_distorted * i.fx + i.u0, y_distorted * i.fy + i.v0);
+
+                assert!((pixel.0 - x as f64).abs() < 1e-6, "pixel ({}, {})", x, y);
+                assert!((pixel.1 - y as f64).abs() < 1e-6, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn default_lens_undistorts_nearly_every_pixel() {
+        let rays = RayTable::new(&DEFAULT_INTRINSICS, DEFAULT_INTRINSICS_SHAPE);
+
+        // Only a few pixels in the far corners lie past the fold
+        assert!(rays.missing() < rays.rays.len() / 100, "{} pixels missing", rays.missing());
+    }
+
+    #[test]
+    fn point_to_pixel_inverts_projection() {
+        let rays = RayTable::new(&DEFAULT_INTRINSICS, DEFAULT_INTRINSICS_SHAPE);
+
+        for (x, y) in [(20, 20), (160, 120), (17, 200), (300, 30), (299, 219)] {
+            let (px, py, pz) = depth_to_point_cloud(x, y, 1500, &rays).unwrap();
+            let point = (f64::from(px), f64::from(py), f64::from(pz));
+
+            let (column, row) = point_to_pixel(point, &DEFAULT_INTRINSICS).unwrap();
+
+            assert!((column - x as f64).abs() < 1e-3, "pixel ({}, {}) -> {}", x, y, column);
+            assert!((row - y as f64).abs() < 1e-3, "pixel ({}, {}) -> {}", x, y, row);
+        }
+        assert_eq!(point_to_pixel((0., 0., -1.), &DEFAULT_INTRINSICS), None);
+    }
 }
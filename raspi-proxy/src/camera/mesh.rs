@@ -0,0 +1,207 @@
+//! Triangle meshes from organized point clouds
+//!
+//! Every 2x2 block of depth pixels gives up to two triangles. Triangles that
+//! span a gap in the surface, e.g. from a foreground edge to the wall behind,
+//! are left out by the edge length and depth jump limits.
+
+use ndarray::Array2;
+
+use crate::camera::{Point, PointArr, PointCloud};
+
+/// Marks depth pixels whose point is not in the mesh yet
+const NO_VERTEX: u32 = u32::MAX;
+
+/// When neighbouring points are joined into a triangle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshConfig {
+    /// Longest triangle edge in metres
+    pub max_edge: f32,
+    /// Largest depth jump along an edge, as a fraction of the nearer depth
+    pub max_depth_jump: f32,
+}
+
+impl Default for MeshConfig {
+    fn default() -> Self {
+        Self {
+            max_edge: 0.05,
+            max_depth_jump: 0.05,
+        }
+    }
+}
+
+impl MeshConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.max_edge.is_nan() || self.max_edge <= 0. {
+            return Err(format!("Mesh edge limit must be positive, got {}", self.max_edge).into());
+        }
+        if self.max_depth_jump.is_nan() || self.max_depth_jump <= 0. {
+            return Err(format!(
+                "Mesh depth jump limit must be positive, got {}",
+                self.max_depth_jump
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Whether two points are close enough to share a triangle edge
+    fn joins(&self, a: &Point, b: &Point) -> bool {
+        let length = ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt();
+        // Depth along the camera axis is -z, see `Point`
+        let jump = (a.z - b.z).abs();
+
+        length <= self.max_edge && jump <= self.max_depth_jump * a.z.abs().min(b.z.abs())
+    }
+}
+
+/// Triangles over the points of one camera frame
+pub struct Mesh {
+    pub frame_id: u64,
+    /// Capture time on the camera's clock
+    pub stamp_msec: u64,
+    /// Only the points some triangle uses
+    pub vertices: PointArr,
+    /// Three vertex indices per triangle, clockwise seen from the camera like
+    /// Godot expects of front faces
+    pub indices: Vec<u32>,
+}
+
+/// Joins neighbouring points of a cloud into triangles
+pub fn triangulate(cloud: &PointCloud, config: &MeshConfig) -> Mesh {
+    let grid = cloud.organized();
+    let (height, width) = grid.dim();
+
+    let mut vertex_of = Array2::from_elem(grid.dim(), NO_VERTEX);
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    let mut add_triangle = |corners: [(usize, usize); 3]| {
+        let [a, b, c] = corners.map(|pixel| &grid[pixel]);
+        if a.z.is_nan() || b.z.is_nan() || c.z.is_nan() {
+            return;
+        }
+        if !(config.joins(a, b) && config.joins(b, c) && config.joins(c, a)) {
+            return;
+        }
+
+        for pixel in corners {
+            if vertex_of[pixel] == NO_VERTEX {
+                vertex_of[pixel] = vertices.len() as u32;
+                vertices.push(grid[pixel]);
+            }
+            indices.push(vertex_of[pixel]);
+        }
+    };
+
+    // Rows run down the image and columns to the right, so top left, top
+    // right, bottom left is clockwise
+    for row in 0..height.saturating_sub(1) {
+        for column in 0..width.saturating_sub(1) {
+            let top_left = (row, column);
+            let top_right = (row, column + 1);
+            let bottom_left = (row + 1, column);
+            let bottom_right = (row + 1, column + 1);
+
+            add_triangle([top_left, top_right, bottom_left]);
+            add_triangle([top_right, bottom_right, bottom_left]);
+        }
+    }
+
+    Mesh {
+        frame_id: cloud.frame_id,
+        stamp_msec: cloud.stamp_msec,
+        vertices,
+        indices,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points `spacing` metres apart on a grid, NaN depths leave a pixel out
+    fn grid_cloud(depths: &[&[f32]], spacing: f32) -> PointCloud {
+        let shape = (depths.len(), depths[0].len());
+        let mut points = Vec::new();
+        let mut pixels = Vec::new();
+
+        for (row, line) in depths.iter().enumerate() {
+            for (column, &depth) in line.iter().enumerate() {
+                if depth.is_nan() {
+                    continue;
+                }
+                points.push(Point {
+                    x: column as f32 * spacing,
+                    y: -(row as f32) * spacing,
+                    z: -depth,
+                    r: 0,
+                    g: 0,
+                    b: 0,
+                });
+                pixels.push((row, column));
+            }
+        }
+
+        PointCloud {
+            frame_id: 7,
+            stamp_msec: 700,
+            points,
+            pixels,
+            shape,
+            low_confidence: 0,
+            flying_pixels: 0,
+        }
+    }
+
+    #[test]
+    fn square_gives_two_clockwise_triangles() {
+        let cloud = grid_cloud(&[&[1., 1.], &[1., 1.]], 0.01);
+
+        let mesh = triangulate(&cloud, &MeshConfig::default());
+
+        assert_eq!(mesh.frame_id, 7);
+        assert_eq!(mesh.stamp_msec, 700);
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, [0, 1, 2, 1, 3, 2]);
+
+        // Clockwise from the camera means the normal points away from it
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize]);
+            let normal_z = (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+            assert!(normal_z < 0., "triangle {:?} is counter-clockwise", triangle);
+        }
+    }
+
+    #[test]
+    fn depth_jump_gives_no_triangle() {
+        let cloud = grid_cloud(&[&[1., 1.], &[1., 1.2]], 0.01);
+
+        let mesh = triangulate(&cloud, &MeshConfig::default());
+
+        // Only the triangle away from the far corner is left
+        assert_eq!(mesh.indices, [0, 1, 2]);
+        assert_eq!(mesh.vertices.len(), 3);
+    }
+
+    #[test]
+    fn long_edge_gives_no_triangle() {
+        let cloud = grid_cloud(&[&[1., 1.], &[1., 1.]], 0.1);
+
+        let mesh = triangulate(&cloud, &MeshConfig::default());
+
+        assert!(mesh.indices.is_empty());
+        assert!(mesh.vertices.is_empty());
+    }
+
+    #[test]
+    fn missing_pixel_is_skipped() {
+        let cloud = grid_cloud(&[&[1., 1., 1.], &[1., 1., f32::NAN]], 0.01);
+
+        let mesh = triangulate(&cloud, &MeshConfig::default());
+
+        // Both triangles of the left square, one of the right
+        assert_eq!(mesh.indices.len(), 9);
+        assert!(mesh.vertices.iter().all(|vertex| !vertex.z.is_nan()));
+    }
+}
@@ -1,8 +1,144 @@
+mod calibration;
+mod connection;
+mod depth_units;
+mod device_info;
 mod fetch_frame;
+mod filters;
 mod intrinsics;
+mod mesh;
+mod pipeline;
+mod projector;
+mod recording;
+mod registration;
+mod source;
+#[allow(clippy::module_inception)]
 mod camera;
 
+use ndarray::Array2;
+
+pub use calibration::{Calibration, CameraCalibration, DEFAULT_CALIBRATION_PATH};
 pub use camera::SipeedCamera;
+pub use device_info::DeviceInfo;
+pub use source::{
+    FrameSource, RecordingSource, ReplayPacing, ReplaySource, SipeedHttpSource, SyntheticScene,
+    SyntheticSource, DEFAULT_HOST, DEFAULT_PORT,
+};
+pub use fetch_frame::{
+    decode_frame, DeepMode, FrameConfig, IrMode, RgbMode, RgbRes, StatusMode, TriggerMode,
+};
+pub use intrinsics::{
+    point_to_pixel, CameraIntrinsics, RayTable, DEFAULT_INTRINSICS, DEFAULT_INTRINSICS_SHAPE,
+};
+pub use mesh::{triangulate, MeshConfig};
+pub use registration::Extrinsics;
+pub use filters::{Discontinuity, FilterConfig};
+
+/// Millimetres per metre, depth comes in millimetres
+const MM_PER_METRE: f32 = 1000.;
+
+/// One coloured point of a cloud
+///
+/// Positions are metres in a right-handed frame centred on the depth camera,
+/// with x to the right, y up and z out of the back of the camera, so the
+/// scene in view has negative z. This is the camera frame of Godot and
+/// OpenXR, so clients use points as they are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Point {
+    /// Stands in for pixels without a point in an organized cloud
+    pub const INVALID: Self = Self {
+        x: f32::NAN,
+        y: f32::NAN,
+        z: f32::NAN,
+        r: 0,
+        g: 0,
+        b: 0,
+    };
+
+    /// Point from the lens model's frame, millimetres with x to the right,
+    /// y down and z forward
+    pub fn from_camera(position: (f32, f32, f32), (r, g, b): (u8, u8, u8)) -> Self {
+        let (x, y, z) = position;
+
+        // Half a turn about x keeps the frame right-handed
+        Self {
+            x: x / MM_PER_METRE,
+            y: -y / MM_PER_METRE,
+            z: -z / MM_PER_METRE,
+            r,
+            g,
+            b,
+        }
+    }
+}
+
+pub type PointArr = Vec<Point>;
+
+/// Points projected from one camera frame
+pub struct PointCloud {
+    pub frame_id: u64,
+    /// Capture time on the camera's clock
+    pub stamp_msec: u64,
+    pub points: PointArr,
+    /// Depth pixel each point came from, as (row, column)
+    pub pixels: Vec<(usize, usize)>,
+    /// Size of the depth image, as (height, width)
+    pub shape: (usize, usize),
+    /// Pixels dropped for a weak IR return before filtering
+    pub low_confidence: usize,
+    /// Pixels dropped as flying pixels before projecting
+    pub flying_pixels: usize,
+}
+
+impl PointCloud {
+    /// The points laid out on the depth image's grid, `Point::INVALID` where
+    /// a pixel has none
+    pub fn organized(&self) -> Array2<Point> {
+        let mut grid = Array2::from_elem(self.shape, Point::INVALID);
+        for (point, &pixel) in self.points.iter().zip(&self.pixels) {
+            grid[pixel] = *point;
+        }
+        grid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn organized_puts_points_on_their_pixels() {
+        let point = |x| Point {
+            x,
+            y: 0.,
+            z: -1.,
+            r: 1,
+            g: 2,
+            b: 3,
+        };
+        let cloud = PointCloud {
+            frame_id: 0,
+            stamp_msec: 0,
+            points: vec![point(1.), point(2.)],
+            pixels: vec![(0, 2), (1, 0)],
+            shape: (2, 3),
+            low_confidence: 0,
+            flying_pixels: 0,
+        };
+
+        let grid = cloud.organized();
 
-pub type Point = (i32, i32, i32, u8, u8, u8);
-pub type PointArr = Vec<Point>;
\ No newline at end of file
+        assert_eq!(grid.dim(), (2, 3));
+        assert_eq!(grid[(0, 2)], point(1.));
+        assert_eq!(grid[(1, 0)], point(2.));
+        assert_eq!(grid.iter().filter(|point| point.z.is_nan()).count(), 4);
+    }
+}
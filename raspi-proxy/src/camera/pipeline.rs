@@ -0,0 +1,54 @@
+use std::{
+    sync::{Condvar, Mutex},
+    time::Duration,
+};
+
+/// Channel between pipeline stages that holds at most one message
+///
+/// Sending never blocks: a new message replaces one that has not been
+/// received yet, so a slow stage always picks up the latest frame.
+pub struct Mailbox<T> {
+    slot: Mutex<Option<T>>,
+    ready: Condvar,
+}
+
+impl<T> Default for Mailbox<T> {
+    fn default() -> Self {
+        Self {
+            slot: Mutex::new(None),
+            ready: Condvar::new(),
+        }
+    }
+}
+
+impl<T> Mailbox<T> {
+    /// Stores `message`, returning true if it replaced an unread one
+    pub fn send(&self, message: T) -> bool {
+        let replaced = self.slot.lock().unwrap().replace(message).is_some();
+        self.ready.notify_one();
+        replaced
+    }
+
+    /// Blocks until a message arrives
+    pub fn recv(&self) -> T {
+        let mut slot = self
+            .ready
+            .wait_while(self.slot.lock().unwrap(), |slot| slot.is_none())
+            .unwrap();
+        slot.take().unwrap()
+    }
+
+    /// Blocks until a message arrives or `timeout` passes
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        let (mut slot, _) = self
+            .ready
+            .wait_timeout_while(self.slot.lock().unwrap(), timeout, |slot| slot.is_none())
+            .unwrap();
+        slot.take()
+    }
+
+    /// Takes a waiting message without blocking
+    pub fn try_recv(&self) -> Option<T> {
+        self.slot.lock().unwrap().take()
+    }
+}
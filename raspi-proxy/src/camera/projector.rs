@@ -0,0 +1,174 @@
+use ndarray::{Array2, Zip};
+
+use crate::camera::{
+    calibration::Calibration,
+    depth_units::DepthUnits,
+    fetch_frame::ProcessedFrames,
+    filters::{
+        confidence, remove_flying_pixels, spatial_filter, FilterConfig, TemporalFilter,
+    },
+    intrinsics::{
+        depth_to_point_cloud, CameraIntrinsics, RayTable, DEFAULT_INTRINSICS,
+        DEFAULT_INTRINSICS_SHAPE,
+    },
+    registration::Registration,
+    Point, PointArr, PointCloud,
+};
+
+/// Turns decoded frames into point clouds, keeping state between frames
+pub struct Projector {
+    temporal: TemporalFilter,
+    calibration: Calibration,
+    /// Intrinsics and rays for the resolution of the last frame
+    intrinsics: CameraIntrinsics,
+    rays: RayTable,
+    registration: Registration,
+}
+
+impl Default for Projector {
+    fn default() -> Self {
+        Self {
+            temporal: TemporalFilter::default(),
+            calibration: Calibration::default(),
+            intrinsics: DEFAULT_INTRINSICS,
+            rays: RayTable::new(&DEFAULT_INTRINSICS, DEFAULT_INTRINSICS_SHAPE),
+            registration: Registration::default(),
+        }
+    }
+}
+
+impl Projector {
+    pub fn project(&mut self, config: &FilterConfig, frames: &ProcessedFrames) -> Option<PointCloud> {
+        let depth = frames.depth.as_ref()?;
+        self.fit_to(depth.dim());
+
+        let status = frames.status.as_ref();
+
+        let (valid, low_confidence) = match (&config.confidence, &frames.ir) {
+            (Some(confidence_config), Some(ir)) if ir.dim() == depth.dim() => {
+                let confidence = confidence(ir, status, confidence_config);
+                let mut low_confidence = 0;
+                let valid = Zip::from(depth).and(&confidence).map_collect(|&d, &c| {
+                    let confident = c >= confidence_config.min_amplitude;
+                    if d != 0 && !confident {
+                        low_confidence += 1;
+                    }
+                    d != 0 && confident
+                });
+                (valid, low_confidence)
+            }
+            (Some(_), _) => {
+                trace!("Frame {} has no usable IR image for confidence", frames.frame_id);
+                (valid_pixels(depth, status), 0)
+            }
+            (None, _) => (valid_pixels(depth, status), 0),
+        };
+        if low_confidence > 0 {
+            debug!("Frame {} dropped {} low confidence pixels", frames.frame_id, low_confidence);
+        }
+
+        // Pixels measured but thrown out, which hole filling must not restore
+        let rejected = Zip::from(depth)
+            .and(&valid)
+            .map_collect(|&d, &valid| d != 0 && !valid);
+
+        let depth = match config.temporal {
+            Some(ref temporal) => self.temporal.apply(temporal, depth, &valid),
+            None => {
+                self.temporal.reset();
+                Zip::from(depth)
+                    .and(&valid)
+                    .map_collect(|&d, &valid| if valid { d } else { 0 })
+            }
+        };
+        let depth = spatial_filter(config, depth, &rejected);
+
+        let (depth, flying_pixels) = match config.flying_pixels {
+            Some(ref flying) => remove_flying_pixels(&depth, flying, self.intrinsics.fx as f32),
+            None => (depth, 0),
+        };
+        if flying_pixels > 0 {
+            debug!("Frame {} dropped {} flying pixels", frames.frame_id, flying_pixels);
+        }
+
+        let mut points: PointArr = Vec::new();
+        let mut pixels = Vec::new();
+
+        for ((row, column), &d) in depth.indexed_iter() {
+            if d == 0 {
+                continue;
+            }
+
+            let surface = DepthUnits::surface(d);
+            let Some((x, y, z)) = depth_to_point_cloud(column, row, surface, &self.rays) else {
+                continue;
+            };
+
+            let colour = frames
+                .rgb
+                .as_ref()
+                .and_then(|rgb| {
+                    self.registration
+                        .colour((f64::from(x), f64::from(y), f64::from(z)), rgb)
+                })
+                .unwrap_or((255, 255, 255));
+
+            points.push(Point::from_camera((x, y, z), colour));
+            pixels.push((row, column));
+        }
+
+        Some(PointCloud {
+            frame_id: frames.frame_id,
+            stamp_msec: frames.stamp_msec,
+            points,
+            pixels,
+            shape: depth.dim(),
+            low_confidence,
+            flying_pixels,
+        })
+    }
+
+    /// Switches to another calibration, rebuilding the rays and registration
+    pub fn set_calibration(&mut self, calibration: &Calibration) {
+        if self.calibration == *calibration {
+            return;
+        }
+
+        self.calibration = *calibration;
+        self.registration = Registration::from_calibration(calibration);
+        let shape = self.rays.dim();
+        self.rebuild_rays(shape);
+    }
+
+    /// Rebuilds the rays when the depth resolution changes
+    fn fit_to(&mut self, shape: (usize, usize)) {
+        if self.rays.dim() != shape {
+            info!("Depth resolution is now {}x{}", shape.1, shape.0);
+            self.rebuild_rays(shape);
+        }
+    }
+
+    fn rebuild_rays(&mut self, shape: (usize, usize)) {
+        let (intrinsics, calibrated_shape) = self
+            .calibration
+            .depth
+            .map(|depth| (depth.intrinsics, depth.shape))
+            .unwrap_or((DEFAULT_INTRINSICS, DEFAULT_INTRINSICS_SHAPE));
+
+        self.intrinsics = intrinsics.scaled(calibrated_shape, shape);
+        self.rays = RayTable::new(&self.intrinsics, shape);
+    }
+}
+
+/// Pixels with a depth reading and a clear status
+///
+/// A non-zero status after the status mask means the camera flagged the pixel,
+/// e.g. as over- or underexposed.
+fn valid_pixels(depth: &Array2<u16>, status: Option<&Array2<u16>>) -> Array2<bool> {
+    match status {
+        Some(status) if status.dim() == depth.dim() => Zip::from(depth)
+            .and(status)
+            .map_collect(|&d, &s| d != 0 && s == 0),
+        _ => depth.mapv(|d| d != 0),
+    }
+}
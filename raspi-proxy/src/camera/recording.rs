@@ -0,0 +1,311 @@
+//! Recorded `/getdeep` bodies in a single indexed file
+//!
+//! ```text
+//! header  "SPRC" | version: u32
+//! frame   frame_id: u64 | stamp_msec: u64 | config: [u8; 12] | len: u32 | body: [u8; len]
+//! ...
+//! index   count: u64 | offset: u64 * count
+//! footer  index_offset: u64 | "SPIX"
+//! ```
+//!
+//! All integers are little endian. The index is written when recording
+//! finishes; files cut short by a crash are read by scanning the frames.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::camera::fetch_frame::FrameConfig;
+
+const MAGIC: &[u8; 4] = b"SPRC";
+const INDEX_MAGIC: &[u8; 4] = b"SPIX";
+const VERSION: u32 = 1;
+
+/// Size of the header and footer in bytes
+const HEADER_LEN: u64 = 8;
+const FOOTER_LEN: u64 = 12;
+
+/// Size of the fixed fields before each frame body
+const FRAME_HEADER_LEN: u64 = 8 + 8 + 12 + 4;
+
+/// One recorded `/getdeep` response
+pub struct RecordedFrame {
+    pub frame_id: u64,
+    pub stamp_msec: u64,
+    /// Config that was applied when the frame was fetched
+    pub config: FrameConfig,
+    pub body: Vec<u8>,
+}
+
+/// Appends frames to a recording file
+pub struct Recorder {
+    writer: BufWriter<File>,
+    offsets: Vec<u64>,
+    position: u64,
+    finished: bool,
+    /// Set by a failed write, which may have left part of a frame behind
+    /// and made `position` wrong
+    poisoned: bool,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_u32::<LittleEndian>(VERSION)?;
+        writer.flush()?;
+
+        info!("Recording frames to {}", path.display());
+
+        Ok(Self {
+            writer,
+            offsets: Vec::new(),
+            position: HEADER_LEN,
+            finished: false,
+            poisoned: false,
+        })
+    }
+
+    /// Appends a frame, stopping the recording at the first error
+    ///
+    /// Frames after an error are dropped silently and no index is written,
+    /// so the file is read back by scanning up to the broken frame.
+    pub fn write_frame(
+        &mut self,
+        frame_id: u64,
+        stamp_msec: u64,
+        config: &FrameConfig,
+        body: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.poisoned {
+            return Ok(());
+        }
+
+        let written = (|| -> Result<(), std::io::Error> {
+            self.writer.write_u64::<LittleEndian>(frame_id)?;
+            self.writer.write_u64::<LittleEndian>(stamp_msec)?;
+            self.writer.write_all(&config.encode())?;
+            self.writer.write_u32::<LittleEndian>(body.len() as u32)?;
+            self.writer.write_all(body)?;
+
+            // Flush every frame so a killed proxy leaves a readable file
+            self.writer.flush()
+        })();
+
+        if let Err(e) = written {
+            self.poisoned = true;
+            return Err(format!("{}, recording stopped", e).into());
+        }
+
+        self.offsets.push(self.position);
+        self.position += FRAME_HEADER_LEN + body.len() as u64;
+
+        Ok(())
+    }
+
+    /// Writes the index, after which no more frames can be added
+    pub fn finish(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.finished || self.poisoned {
+            return Ok(());
+        }
+        self.finished = true;
+
+        self.writer.write_u64::<LittleEndian>(self.offsets.len() as u64)?;
+        for offset in &self.offsets {
+            self.writer.write_u64::<LittleEndian>(*offset)?;
+        }
+
+        self.writer.write_u64::<LittleEndian>(self.position)?;
+        self.writer.write_all(INDEX_MAGIC)?;
+        self.writer.flush()?;
+
+        info!("Recorded {} frames", self.offsets.len());
+
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            warn!("Error finishing recording: {}", e);
+        }
+    }
+}
+
+/// Random access to the frames of a recording file
+pub struct Recording {
+    reader: BufReader<File>,
+    offsets: Vec<u64>,
+}
+
+impl Recording {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(format!("{} is not a recording", path.display()).into());
+        }
+
+        let version = reader.read_u32::<LittleEndian>()?;
+        if version != VERSION {
+            return Err(format!("Unsupported recording version {}", version).into());
+        }
+
+        let offsets = match read_index(&mut reader)? {
+            Some(offsets) => offsets,
+            None => {
+                warn!("{} has no index, scanning frames", path.display());
+                scan_frames(&mut reader)?
+            }
+        };
+
+        Ok(Self { reader, offsets })
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    pub fn frame(&mut self, index: usize) -> Result<RecordedFrame, Box<dyn std::error::Error>> {
+        let offset = *self
+            .offsets
+            .get(index)
+            .ok_or_else(|| format!("Frame {} out of range", index))?;
+        self.reader.seek(SeekFrom::Start(offset))?;
+
+        let frame_id = self.reader.read_u64::<LittleEndian>()?;
+        let stamp_msec = self.reader.read_u64::<LittleEndian>()?;
+        let mut config = [0u8; 12];
+        self.reader.read_exact(&mut config)?;
+        let len = self.reader.read_u32::<LittleEndian>()?;
+        let mut body = vec![0u8; len as usize];
+        self.reader.read_exact(&mut body)?;
+
+        Ok(RecordedFrame {
+            frame_id,
+            stamp_msec,
+            config: FrameConfig::decode(&config)?,
+            body,
+        })
+    }
+}
+
+/// Reads the index from the footer, if the recording was finished
+fn read_index(reader: &mut BufReader<File>) -> Result<Option<Vec<u64>>, Box<dyn std::error::Error>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    if len < HEADER_LEN + FOOTER_LEN {
+        return Ok(None);
+    }
+
+    reader.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+    let index_offset = reader.read_u64::<LittleEndian>()?;
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != INDEX_MAGIC || index_offset >= len {
+        return Ok(None);
+    }
+
+    reader.seek(SeekFrom::Start(index_offset))?;
+    let count = reader.read_u64::<LittleEndian>()?;
+    let mut offsets = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        offsets.push(reader.read_u64::<LittleEndian>()?);
+    }
+
+    Ok(Some(offsets))
+}
+
+/// Walks the frames from the start, stopping at the first incomplete one
+fn scan_frames(reader: &mut BufReader<File>) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let mut offsets = Vec::new();
+    let mut position = HEADER_LEN;
+
+    while position + FRAME_HEADER_LEN <= len {
+        reader.seek(SeekFrom::Start(position + FRAME_HEADER_LEN - 4))?;
+        let body_len = reader.read_u32::<LittleEndian>()? as u64;
+
+        let next = position + FRAME_HEADER_LEN + body_len;
+        if next > len {
+            break;
+        }
+
+        offsets.push(position);
+        position = next;
+    }
+
+    Ok(offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::fetch_frame::DeepMode;
+
+    /// A path in the temp directory no other test uses
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("raspi-proxy-{}-{}.rec", name, std::process::id()))
+    }
+
+    fn record(path: &Path, config: &FrameConfig) {
+        let mut recorder = Recorder::create(path).unwrap();
+        for frame_id in 0..3u64 {
+            let body = vec![frame_id as u8; 10 + frame_id as usize];
+            recorder.write_frame(frame_id, frame_id * 66, config, &body).unwrap();
+        }
+    }
+
+    fn assert_frames(recording: &mut Recording, config: &FrameConfig, count: usize) {
+        assert_eq!(recording.len(), count);
+        for index in 0..count {
+            let frame = recording.frame(index).unwrap();
+            assert_eq!(frame.frame_id, index as u64);
+            assert_eq!(frame.stamp_msec, index as u64 * 66);
+            assert_eq!(frame.config, *config);
+            assert_eq!(frame.body, vec![index as u8; 10 + index]);
+        }
+    }
+
+    #[test]
+    fn indexed_round_trip() {
+        let path = temp_path("indexed");
+        let config = FrameConfig::builder().deep_mode(DeepMode::Bits8).build().unwrap();
+
+        record(&path, &config);
+        let mut recording = Recording::open(&path).unwrap();
+
+        assert_frames(&mut recording, &config, 3);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unindexed_recording_is_scanned() {
+        let path = temp_path("unindexed");
+        let config = FrameConfig::default();
+
+        // Cut off the index and half of the last frame, like a crash would
+        record(&path, &config);
+        let two_frames = HEADER_LEN + 2 * FRAME_HEADER_LEN + 10 + 11;
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(two_frames + FRAME_HEADER_LEN + 5)
+            .unwrap();
+        let mut recording = Recording::open(&path).unwrap();
+
+        assert_frames(&mut recording, &config, 2);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+//! Colouring depth points from the RGB camera
+//!
+//! The RGB camera sits next to the ToF sensor, so the two see the scene from
+//! slightly different places. Every point is moved into the RGB camera's
+//! frame with the extrinsics and projected with its intrinsics, which keeps
+//! the colours aligned at every distance.
+
+use ndarray::Array3;
+
+use crate::camera::{
+    calibration::Calibration,
+    intrinsics::{point_to_pixel, CameraIntrinsics},
+};
+
+/// Intrinsics of the RGB camera, without distortion until it is calibrated
+///
+/// The focal length and offsets are derived from the hand-tuned mapping this
+/// replaced, 1.75 RGB pixels per depth pixel, assuming it was tuned at 1 m.
+pub static DEFAULT_RGB_INTRINSICS: CameraIntrinsics = CameraIntrinsics {
+    fx: 4.057e+02,
+    fy: 4.074e+02,
+    u0: 3.195e+02,
+    v0: 2.395e+02,
+    k1: 0.,
+    k2: 0.,
+    k3: 0.,
+    p1: 0.,
+    p2: 0.,
+};
+
+/// RGB image size `DEFAULT_RGB_INTRINSICS` are for, as (height, width)
+pub const DEFAULT_RGB_INTRINSICS_SHAPE: (usize, usize) = (480, 640);
+
+/// Pose of the RGB camera relative to the depth camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extrinsics {
+    /// Rotation from depth camera to RGB camera coordinates, row major
+    pub rotation: [[f64; 3]; 3],
+    /// Translation in millimetres, applied after the rotation
+    pub translation: [f64; 3],
+}
+
+/// Puts the RGB camera where the hand-tuned offsets placed it at 1 m
+pub static DEFAULT_EXTRINSICS: Extrinsics = Extrinsics {
+    rotation: [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]],
+    translation: [27.5, 71.6, 0.],
+};
+
+impl Extrinsics {
+    /// Moves a point from depth camera to RGB camera coordinates
+    pub fn transform(&self, point: (f64, f64, f64)) -> (f64, f64, f64) {
+        let p = [point.0, point.1, point.2];
+        let [r0, r1, r2] = self.rotation;
+        let dot = |row: [f64; 3]| row[0] * p[0] + row[1] * p[1] + row[2] * p[2];
+
+        (
+            dot(r0) + self.translation[0],
+            dot(r1) + self.translation[1],
+            dot(r2) + self.translation[2],
+        )
+    }
+}
+
+/// Maps depth camera points to colours in the RGB image
+pub struct Registration {
+    /// Intrinsics at the image size they were calibrated at, as (height, width)
+    intrinsics: CameraIntrinsics,
+    shape: (usize, usize),
+    extrinsics: Extrinsics,
+    /// Intrinsics scaled to the last RGB image and its (height, width)
+    scaled: (CameraIntrinsics, (usize, usize)),
+}
+
+impl Default for Registration {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_RGB_INTRINSICS,
+            DEFAULT_RGB_INTRINSICS_SHAPE,
+            DEFAULT_EXTRINSICS,
+        )
+    }
+}
+
+impl Registration {
+    pub fn new(
+        intrinsics: CameraIntrinsics,
+        shape: (usize, usize),
+        extrinsics: Extrinsics,
+    ) -> Self {
+        Self {
+            intrinsics,
+            shape,
+            extrinsics,
+            scaled: (intrinsics, shape),
+        }
+    }
+
+    /// Registration from a calibration, with defaults for what it lacks
+    pub fn from_calibration(calibration: &Calibration) -> Self {
+        let (intrinsics, shape) = calibration
+            .rgb
+            .map(|rgb| (rgb.intrinsics, rgb.shape))
+            .unwrap_or((DEFAULT_RGB_INTRINSICS, DEFAULT_RGB_INTRINSICS_SHAPE));
+
+        Self::new(intrinsics, shape, calibration.extrinsics.unwrap_or(DEFAULT_EXTRINSICS))
+    }
+
+    /// Colour of the RGB image where a depth camera point lands, None for
+    /// points outside it
+    pub fn colour(&mut self, point: (f64, f64, f64), rgb: &Array3<u8>) -> Option<(u8, u8, u8)> {
+        let (height, width, _) = rgb.dim();
+        if self.scaled.1 != (height, width) {
+            let intrinsics = self.intrinsics.scaled(self.shape, (height, width));
+            self.scaled = (intrinsics, (height, width));
+        }
+
+        let (u, v) = point_to_pixel(self.extrinsics.transform(point), &self.scaled.0)?;
+        sample_bilinear(rgb, u, v)
+    }
+}
+
+/// Interpolates the RGB image between the four pixels around (u, v)
+fn sample_bilinear(rgb: &Array3<u8>, u: f64, v: f64) -> Option<(u8, u8, u8)> {
+    let (height, width, _) = rgb.dim();
+    if !(u >= 0.0 && v >= 0.0 && u <= (width - 1) as f64 && v <= (height - 1) as f64) {
+        return None;
+    }
+
+    // Clamp so the right and bottom edges still have a pixel to blend with
+    let column = (u.floor() as usize).min(width.saturating_sub(2));
+    let row = (v.floor() as usize).min(height.saturating_sub(2));
+    let (fu, fv) = (u - column as f64, v - row as f64);
+
+    let channel = |c: usize| {
+        let top = f64::from(rgb[(row, column, c)]) * (1.0 - fu)
+            + f64::from(rgb[(row, column + 1, c)]) * fu;
+        let bottom = f64::from(rgb[(row + 1, column, c)]) * (1.0 - fu)
+            + f64::from(rgb[(row + 1, column + 1, c)]) * fu;
+        (top * (1.0 - fv) + bottom * fv).round() as u8
+    };
+
+    Some((channel(0), channel(1), channel(2)))
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use ureq::Agent;
+
+use crate::camera::{
+    device_info::DeviceInfo,
+    fetch_frame::{decode_frame_header, FrameConfig},
+    source::FrameSource,
+};
+
+/// Address of the camera on its USB network interface
+pub const DEFAULT_HOST: &str = "192.168.233.1";
+pub const DEFAULT_PORT: u16 = 80;
+
+/// Limits on each request so an unplugged camera fails fast
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A MaixSense camera reached over its HTTP interface
+pub struct SipeedHttpSource {
+    host: String,
+    port: u16,
+    /// Reused between requests to keep the connection alive
+    agent: Agent,
+}
+
+impl Default for SipeedHttpSource {
+    fn default() -> Self {
+        Self::new(DEFAULT_HOST, DEFAULT_PORT)
+    }
+}
+
+impl SipeedHttpSource {
+    pub fn new(host: &str, port: u16) -> Self {
+        let agent = Agent::config_builder()
+            .timeout_connect(Some(CONNECT_TIMEOUT))
+            .timeout_global(Some(REQUEST_TIMEOUT))
+            .build()
+            .into();
+
+        Self {
+            host: host.to_string(),
+            port,
+            agent,
+        }
+    }
+}
+
+impl FrameSource for SipeedHttpSource {
+    fn apply_config(&mut self, config: &FrameConfig) -> Result<(), Box<dyn std::error::Error>> {
+        let url = format!("http://{}:{}/set_cfg", self.host, self.port);
+
+        trace!("Sending request to: {}", url);
+
+        // Statuses other than 2xx come back as `ureq::Error::StatusCode`
+        self.agent.post(url).send(config.encode())?;
+        Ok(())
+    }
+
+    fn fetch_frame(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let url = format!("http://{}:{}/getdeep", self.host, self.port);
+
+        trace!("Fetching images from: {}", url);
+
+        let response = self.agent.get(url).call()?;
+
+        trace!("Got deep image");
+        let deep_img = response.into_body().read_to_vec()?;
+        trace!("Length={}", deep_img.len());
+
+        // Parse frame ID and timestamp
+        if let Ok((frame_id, stamp_msec)) = decode_frame_header(&deep_img) {
+            trace!(
+                "Frame ID: {}, Timestamp: {:.3}s",
+                frame_id,
+                stamp_msec as f64 / 1000.0
+            );
+        }
+
+        Ok(deep_img)
+    }
+
+    fn device_info(&mut self) -> Result<Option<DeviceInfo>, Box<dyn std::error::Error>> {
+        let url = format!("http://{}:{}/getinfo", self.host, self.port);
+
+        trace!("Fetching device info from: {}", url);
+
+        // Older firmware has no info page
+        let response = match self.agent.get(url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::StatusCode(404)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let text = response.into_body().read_to_string()?;
+        Ok(Some(DeviceInfo::parse(&text)?))
+    }
+}
@@ -0,0 +1,45 @@
+mod http;
+mod recording;
+mod replay;
+mod synthetic;
+
+pub use http::{SipeedHttpSource, DEFAULT_HOST, DEFAULT_PORT};
+pub use recording::RecordingSource;
+pub use replay::{ReplayPacing, ReplaySource};
+pub use synthetic::{SyntheticScene, SyntheticSource};
+
+use std::sync::{atomic::AtomicBool, Arc};
+
+use crate::camera::device_info::DeviceInfo;
+use crate::camera::fetch_frame::FrameConfig;
+
+/// Something that produces raw `/getdeep` bodies for `decode_frame`
+pub trait FrameSource: Send {
+    /// Sends the capture config, called whenever it changes
+    fn apply_config(&mut self, config: &FrameConfig) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Blocks until the next raw frame is available
+    fn fetch_frame(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+
+    /// Whether frames echo the config passed to `apply_config`
+    fn honours_config(&self) -> bool {
+        true
+    }
+
+    /// Whether a repeated frame id means no new frame was captured yet
+    ///
+    /// Sources that go back over old frames, like a looping replay, repeat
+    /// ids without stalling.
+    fn live_frame_ids(&self) -> bool {
+        true
+    }
+
+    /// Takes a flag that is set when the pipeline shuts down, for sources
+    /// that wait a long time in `fetch_frame` to give up early
+    fn watch_shutdown(&mut self, _shutdown: Arc<AtomicBool>) {}
+
+    /// Serial number and factory lens of the camera, if the source has any
+    fn device_info(&mut self) -> Result<Option<DeviceInfo>, Box<dyn std::error::Error>> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    path::Path,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use crate::camera::{
+    device_info::DeviceInfo,
+    fetch_frame::{decode_frame_header, FrameConfig},
+    recording::Recorder,
+    source::FrameSource,
+};
+
+/// Saves every frame of another source to a recording file
+pub struct RecordingSource {
+    inner: Box<dyn FrameSource>,
+    recorder: Recorder,
+    config: FrameConfig,
+}
+
+impl RecordingSource {
+    pub fn new(
+        inner: Box<dyn FrameSource>,
+        path: &Path,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            inner,
+            recorder: Recorder::create(path)?,
+            config: FrameConfig::default(),
+        })
+    }
+}
+
+impl FrameSource for RecordingSource {
+    fn apply_config(&mut self, config: &FrameConfig) -> Result<(), Box<dyn std::error::Error>> {
+        self.inner.apply_config(config)?;
+        self.config = *config;
+        Ok(())
+    }
+
+    fn fetch_frame(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let frame = self.inner.fetch_frame()?;
+
+        // A broken recording should not take the live stream down with it
+        match decode_frame_header(&frame) {
+            Ok((frame_id, stamp_msec)) => {
+                if let Err(e) = self
+                    .recorder
+                    .write_frame(frame_id, stamp_msec, &self.config, &frame)
+                {
+                    warn!("Error recording frame {}: {}", frame_id, e);
+                }
+            }
+            Err(e) => warn!("Not recording frame: {}", e),
+        }
+
+        Ok(frame)
+    }
+
+    fn honours_config(&self) -> bool {
+        self.inner.honours_config()
+    }
+
+    fn live_frame_ids(&self) -> bool {
+        self.inner.live_frame_ids()
+    }
+
+    fn watch_shutdown(&mut self, shutdown: Arc<AtomicBool>) {
+        self.inner.watch_shutdown(shutdown);
+    }
+
+    fn device_info(&mut self) -> Result<Option<DeviceInfo>, Box<dyn std::error::Error>> {
+        self.inner.device_info()
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    io::BufRead,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::camera::{
+    fetch_frame::FrameConfig,
+    recording::Recording,
+    source::FrameSource,
+};
+
+/// How often a waiting replay checks whether the pipeline is shutting down
+const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+/// How fast a recording is played back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayPacing {
+    /// Follow the recorded timestamps, sped up by the given factor
+    Timed(f64),
+    /// Return frames as fast as they are asked for
+    Unthrottled,
+    /// Wait for Enter on stdin before every frame
+    Step,
+}
+
+/// Replays a recording file made with `--record`, looping at the end
+///
+/// Frames keep the config they were captured with, so `apply_config` has no
+/// effect.
+pub struct ReplaySource {
+    recording: Recording,
+    pacing: ReplayPacing,
+    next: usize,
+    /// When the previous frame was returned and its recorded timestamp
+    previous: Option<(Instant, u64)>,
+    config: Option<FrameConfig>,
+    shutdown: Arc<AtomicBool>,
+    /// Enter presses read by a helper thread in step mode, so waiting for
+    /// one can be given up on
+    steps: Option<Receiver<()>>,
+}
+
+impl ReplaySource {
+    pub fn open(path: &Path, pacing: ReplayPacing) -> Result<Self, Box<dyn std::error::Error>> {
+        let recording = Recording::open(path)?;
+
+        if recording.is_empty() {
+            return Err(format!("No frames in {}", path.display()).into());
+        }
+
+        info!(
+            "Replaying {} frames from {} ({:?})",
+            recording.len(),
+            path.display(),
+            pacing
+        );
+
+        Ok(Self {
+            recording,
+            pacing,
+            next: 0,
+            previous: None,
+            config: None,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            steps: None,
+        })
+    }
+
+    /// Blocks until the next frame is due
+    fn wait(&mut self, stamp_msec: u64) -> Result<(), Box<dyn std::error::Error>> {
+        match self.pacing {
+            ReplayPacing::Timed(speed) => {
+                if let Some((returned, previous_stamp)) = self.previous {
+                    let recorded = Duration::from_millis(stamp_msec.saturating_sub(previous_stamp));
+                    let due = returned + recorded.div_f64(speed);
+
+                    loop {
+                        check_shutdown(&self.shutdown)?;
+                        let left = due.saturating_duration_since(Instant::now());
+                        if left.is_zero() {
+                            break;
+                        }
+                        thread::sleep(left.min(SHUTDOWN_POLL));
+                    }
+                }
+            }
+            ReplayPacing::Unthrottled => {}
+            ReplayPacing::Step => {
+                info!("Press Enter for frame {}/{}", self.next + 1, self.recording.len());
+
+                let steps = self.steps.get_or_insert_with(spawn_step_reader);
+                loop {
+                    check_shutdown(&self.shutdown)?;
+                    match steps.recv_timeout(SHUTDOWN_POLL) {
+                        Ok(()) => break,
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => {
+                            return Err("stdin closed while stepping through replay".into());
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FrameSource for ReplaySource {
+    fn apply_config(&mut self, config: &FrameConfig) -> Result<(), Box<dyn std::error::Error>> {
+        debug!("Ignoring config {:?} during replay", config);
+        Ok(())
+    }
+
+    fn fetch_frame(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let frame = self.recording.frame(self.next)?;
+
+        self.wait(frame.stamp_msec)?;
+
+        if self.config != Some(frame.config) {
+            info!("Replayed frames switch to config {:?}", frame.config);
+            self.config = Some(frame.config);
+        }
+
+        trace!(
+            "Replaying frame {} recorded at {:.3}s",
+            frame.frame_id,
+            frame.stamp_msec as f64 / 1000.0
+        );
+
+        self.next += 1;
+        if self.next == self.recording.len() {
+            debug!("Replay reached the end, looping");
+            self.next = 0;
+            self.previous = None;
+        } else {
+            self.previous = Some((Instant::now(), frame.stamp_msec));
+        }
+
+        Ok(frame.body)
+    }
+
+    fn honours_config(&self) -> bool {
+        false
+    }
+
+    fn live_frame_ids(&self) -> bool {
+        false
+    }
+
+    fn watch_shutdown(&mut self, shutdown: Arc<AtomicBool>) {
+        self.shutdown = shutdown;
+    }
+}
+
+fn check_shutdown(shutdown: &AtomicBool) -> Result<(), Box<dyn std::error::Error>> {
+    if shutdown.load(Ordering::Relaxed) {
+        Err("replay interrupted by shutdown".into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Reads Enter presses from stdin on a thread of its own
+///
+/// The thread is never joined, a read blocked on stdin cannot be cancelled
+/// and it ends with the process.
+fn spawn_step_reader() -> Receiver<()> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::Builder::new()
+        .name("replay-stdin".to_string())
+        .spawn(move || {
+            let mut line = String::new();
+            while std::io::stdin().lock().read_line(&mut line).is_ok_and(|read| read > 0) {
+                line.clear();
+                if sender.send(()).is_err() {
+                    return;
+                }
+            }
+        })
+        .expect("Failed to spawn stdin thread");
+
+    receiver
+}
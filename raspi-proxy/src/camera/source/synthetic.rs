@@ -0,0 +1,438 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use ndarray::{Array2, Array3};
+
+use crate::camera::{
+    device_info::DeviceInfo,
+    fetch_frame::{encode_frame, FrameConfig, ProcessedFrames, Resolution},
+    intrinsics::{CameraIntrinsics, RayTable, DEFAULT_INTRINSICS_SHAPE},
+    registration::Extrinsics,
+    source::FrameSource,
+};
+
+/// Delay between generated frames, roughly the camera's own frame rate
+const FRAME_INTERVAL: Duration = Duration::from_millis(66);
+
+/// Distance to the back wall in millimetres
+const WALL_DEPTH: f32 = 2000.;
+/// Distance to the centre of the orbiting ball in millimetres
+const BALL_DEPTH: f32 = 900.;
+/// Radius of the ball in pixels at 320x240, the scene scales with resolution
+const BALL_RADIUS: f32 = 40.;
+/// Squared relative radius out to which the ball's edge bleeds into the wall
+const FLYING_RING: f32 = 1.1;
+
+/// Lens of the depth camera in the checkerboard scene, a little off
+/// `DEFAULT_INTRINSICS` like a real unit
+pub static SYNTHETIC_DEPTH_INTRINSICS: CameraIntrinsics = CameraIntrinsics {
+    fx: 228.4,
+    fy: 229.9,
+    u0: 163.1,
+    v0: 121.2,
+    k1: 0.05,
+    k2: 0.03,
+    k3: -0.25,
+    p1: 0.0004,
+    p2: 0.0012,
+};
+
+/// Lens of the RGB camera in the checkerboard scene, for checking calibrations
+pub static SYNTHETIC_RGB_INTRINSICS: CameraIntrinsics = CameraIntrinsics {
+    fx: 520.,
+    fy: 518.,
+    u0: 322.5,
+    v0: 236.8,
+    k1: 0.08,
+    k2: -0.15,
+    k3: 0.,
+    p1: 0.001,
+    p2: -0.0005,
+};
+
+/// RGB image size `SYNTHETIC_RGB_INTRINSICS` are for, as (height, width)
+pub const SYNTHETIC_RGB_INTRINSICS_SHAPE: (usize, usize) = (480, 640);
+
+/// Pose of the RGB camera in the checkerboard scene, turned by 1, -2 and 0.5
+/// degrees about x, y and z
+pub static SYNTHETIC_EXTRINSICS: Extrinsics = Extrinsics {
+    rotation: [
+        [0.999352773, -0.009334263, -0.034740554],
+        [0.008721220, 0.999804309, -0.017756247],
+        [0.034899497, 0.017441775, 0.999238615],
+    ],
+    translation: [-25., 1.5, 3.],
+};
+
+/// Serial number the synthetic source reports
+const SYNTHETIC_SERIAL: &str = "SYNTHETIC";
+
+/// What the synthetic source shows
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SyntheticScene {
+    /// A ball orbiting in front of a checkered wall
+    #[default]
+    Ball,
+    /// A checkerboard turning in front of both cameras, for calibration
+    ///
+    /// The depth camera uses `SYNTHETIC_DEPTH_INTRINSICS` at 320x240, the RGB
+    /// camera `SYNTHETIC_RGB_INTRINSICS` and `SYNTHETIC_EXTRINSICS`.
+    Checkerboard {
+        /// Inner corners along the board
+        columns: usize,
+        rows: usize,
+        square_mm: f64,
+    },
+}
+
+/// Generates frames of a `SyntheticScene`
+///
+/// Frames are encoded with whatever config was last applied, so they exercise
+/// the same decoding path as a real camera.
+pub struct SyntheticSource {
+    scene: SyntheticScene,
+    config: FrameConfig,
+    resolution: Resolution,
+    frame_id: u64,
+    start: Instant,
+    /// Rays of both cameras at twice the image size, for the checkerboard
+    supersampled: Option<(RayTable, RayTable)>,
+}
+
+impl Default for SyntheticSource {
+    fn default() -> Self {
+        Self::new(SyntheticScene::default())
+    }
+}
+
+impl SyntheticSource {
+    pub fn new(scene: SyntheticScene) -> Self {
+        Self {
+            scene,
+            config: FrameConfig::default(),
+            resolution: Resolution::A075,
+            frame_id: 0,
+            start: Instant::now(),
+            supersampled: None,
+        }
+    }
+
+    /// Renders the scene at `t` seconds
+    fn render(&mut self, t: f32) -> ProcessedFrames {
+        match self.scene {
+            SyntheticScene::Ball => self.render_ball(t),
+            SyntheticScene::Checkerboard {
+                columns,
+                rows,
+                square_mm,
+            } => self.render_checkerboard(f64::from(t), (columns, rows), square_mm),
+        }
+    }
+
+    fn render_ball(&self, t: f32) -> ProcessedFrames {
+        let resolution = self.resolution;
+        let shape = resolution.shape();
+
+        // The scene is laid out in 320x240 coordinates
+        let to_scene_x = 320. / resolution.width as f32;
+        let to_scene_y = 240. / resolution.height as f32;
+
+        let ball_x = 160. + 80. * t.cos();
+        let ball_y = 120. + 40. * t.sin();
+
+        // Depth in millimetres and whether the pixel hit the ball
+        let sample = |x: f32, y: f32| -> (f32, bool) {
+            let d2 = ((x - ball_x).powi(2) + (y - ball_y).powi(2)) / BALL_RADIUS.powi(2);
+            if d2 < 1. {
+                (BALL_DEPTH - 200. * (1. - d2).sqrt(), true)
+            } else if d2 < FLYING_RING {
+                // Mixed returns around the silhouette, like a real ToF sensor
+                let t = (d2 - 1.) / (FLYING_RING - 1.);
+                (BALL_DEPTH + t * (WALL_DEPTH - BALL_DEPTH), false)
+            } else {
+                // Tilt the wall slightly so it is not a flat constant
+                (WALL_DEPTH + 2. * (x - 160.), false)
+            }
+        };
+
+        let depth = Array2::from_shape_fn(shape, |(y, x)| {
+            sample(x as f32 * to_scene_x, y as f32 * to_scene_y).0 as u16
+        });
+
+        // IR falls off with distance like the real emitter, and a dark patch
+        // on the wall reflects hardly any of it
+        let ir = Array2::from_shape_fn(shape, |(y, x)| {
+            let (x, y) = (x as f32 * to_scene_x, y as f32 * to_scene_y);
+            let (z, ball) = sample(x, y);
+            let dark_patch = (240.0..300.0).contains(&x) && (20.0..80.0).contains(&y);
+            let reflectance = if dark_patch && !ball { 0.005 } else { 1. };
+            (reflectance * 4.0e6 / z).min(u16::MAX as f32) as u16
+        });
+
+        // Flag the ball's silhouette the way the camera flags weak returns
+        let status = Array2::from_shape_fn(shape, |(y, x)| {
+            let (x, y) = (x as f32 * to_scene_x, y as f32 * to_scene_y);
+            let d2 = ((x - ball_x).powi(2) + (y - ball_y).powi(2)) / BALL_RADIUS.powi(2);
+            u16::from((0.9..1.).contains(&d2))
+        });
+
+        let (width, height) = self.config.rgb_res().dimensions();
+        let scale_x = 320. / width as f32;
+        let scale_y = 240. / height as f32;
+        let rgb = Array3::from_shape_fn((height, width, 3), |(y, x, c)| {
+            let (_, ball) = sample(x as f32 * scale_x, y as f32 * scale_y);
+            if ball {
+                [220, 40, 40][c]
+            } else if (x / 40 + y / 40) % 2 == 0 {
+                200
+            } else {
+                60
+            }
+        });
+
+        ProcessedFrames {
+            resolution,
+            depth: Some(depth),
+            ir: Some(ir),
+            status: Some(status),
+            rgb: Some(rgb),
+            ..Default::default()
+        }
+    }
+
+    /// Ray traces the board with 2x2 samples per pixel, so corners land on
+    /// subpixel positions like in a real image
+    fn render_checkerboard(
+        &mut self,
+        t: f64,
+        (columns, rows): (usize, usize),
+        square_mm: f64,
+    ) -> ProcessedFrames {
+        let shape = self.resolution.shape();
+        let (width, height) = self.config.rgb_res().dimensions();
+        let fine = |(height, width): (usize, usize)| (2 * height, 2 * width);
+
+        let stale = match self.supersampled {
+            Some((ref depth, ref rgb)) => {
+                depth.dim() != fine(shape) || rgb.dim() != fine((height, width))
+            }
+            None => true,
+        };
+        if stale {
+            let depth = SYNTHETIC_DEPTH_INTRINSICS.scaled(DEFAULT_INTRINSICS_SHAPE, fine(shape));
+            let rgb = SYNTHETIC_RGB_INTRINSICS
+                .scaled(SYNTHETIC_RGB_INTRINSICS_SHAPE, fine((height, width)));
+            self.supersampled = Some((
+                RayTable::new(&depth, fine(shape)),
+                RayTable::new(&rgb, fine((height, width))),
+            ));
+        }
+        let Some((ref depth_rays, ref rgb_rays)) = self.supersampled else {
+            unreachable!("rays were just built")
+        };
+
+        let board = BoardPose::at(t, (columns, rows), square_mm);
+
+        let depth_camera = Array2::from_shape_fn(shape, |pixel| {
+            board.sample(depth_rays, pixel, [0.; 3], |ray| ray)
+        });
+        let depth = depth_camera.mapv(|hit| hit.map_or(0, |(z, _)| z as u16));
+        let ir = depth_camera.mapv(|hit| {
+            hit.map_or(0, |(z, reflectance)| {
+                (reflectance * 4.0e6 / z).min(f64::from(u16::MAX)) as u16
+            })
+        });
+
+        // RGB rays start at the RGB camera and are turned into depth camera
+        // coordinates, inverting the extrinsics
+        let [r0, r1, r2] = SYNTHETIC_EXTRINSICS.rotation;
+        let inverse = |v: [f64; 3]| {
+            [
+                r0[0] * v[0] + r1[0] * v[1] + r2[0] * v[2],
+                r0[1] * v[0] + r1[1] * v[1] + r2[1] * v[2],
+                r0[2] * v[0] + r1[2] * v[1] + r2[2] * v[2],
+            ]
+        };
+        let t_rgb = SYNTHETIC_EXTRINSICS.translation;
+        let origin = inverse([-t_rgb[0], -t_rgb[1], -t_rgb[2]]);
+
+        let rgb_camera = Array2::from_shape_fn((height, width), |pixel| {
+            board
+                .sample(rgb_rays, pixel, origin, inverse)
+                .map_or(0., |(_, reflectance)| reflectance)
+        });
+        let rgb = Array3::from_shape_fn((height, width, 3), |(y, x, c)| {
+            let tint = [1., 0.95, 0.9][c];
+            (255. * tint * rgb_camera[(y, x)].min(1.)) as u8
+        });
+
+        ProcessedFrames {
+            resolution: self.resolution,
+            depth: Some(depth),
+            ir: Some(ir),
+            status: Some(Array2::zeros(shape)),
+            rgb: Some(rgb),
+            ..Default::default()
+        }
+    }
+}
+
+/// Where the checkerboard is, for `SyntheticScene::Checkerboard`
+struct BoardPose {
+    /// Board to depth camera rotation, row major
+    rotation: [[f64; 3]; 3],
+    /// Depth camera position of the first inner corner
+    translation: [f64; 3],
+    columns: usize,
+    rows: usize,
+    square_mm: f64,
+}
+
+impl BoardPose {
+    /// Swings the board across the view, 45-75 cm away and tilted up to 30
+    /// degrees, never quite repeating
+    fn at(t: f64, (columns, rows): (usize, usize), square_mm: f64) -> Self {
+        let (sx, cx) = (0.5 * (0.7 * t).sin()).sin_cos();
+        let (sy, cy) = (0.5 * (0.45 * t + 1.).sin()).sin_cos();
+        let (sz, cz) = (0.3 * (0.3 * t).sin()).sin_cos();
+
+        // Rz * Ry * Rx
+        let rotation = [
+            [cz * cy, cz * sy * sx - sz * cx, cz * sy * cx + sz * sx],
+            [sz * cy, sz * sy * sx + cz * cx, sz * sy * cx - cz * sx],
+            [-sy, cy * sx, cy * cx],
+        ];
+
+        // Keep the middle of the board on the path
+        let centre = [
+            160. * (0.5 * t).sin(),
+            100. * (0.6 * t).cos(),
+            600. + 150. * (0.35 * t).sin(),
+        ];
+        let middle = [
+            (columns - 1) as f64 * square_mm / 2.,
+            (rows - 1) as f64 * square_mm / 2.,
+        ];
+        let translation =
+            [0, 1, 2].map(|i| centre[i] - rotation[i][0] * middle[0] - rotation[i][1] * middle[1]);
+
+        Self {
+            rotation,
+            translation,
+            columns,
+            rows,
+            square_mm,
+        }
+    }
+
+    /// Mean depth and reflectance of a pixel's 2x2 samples, None without rays
+    ///
+    /// `turn` takes rays from the camera's to depth camera coordinates.
+    fn sample(
+        &self,
+        rays: &RayTable,
+        (y, x): (usize, usize),
+        origin: [f64; 3],
+        turn: impl Fn([f64; 3]) -> [f64; 3],
+    ) -> Option<(f64, f64)> {
+        let hits: Vec<(f64, f64)> = [(0, 0), (0, 1), (1, 0), (1, 1)]
+            .into_iter()
+            .filter_map(|(dy, dx)| rays.ray(2 * x + dx, 2 * y + dy))
+            .map(|(x_norm, y_norm)| {
+                self.trace(origin, turn([f64::from(x_norm), f64::from(y_norm), 1.]))
+            })
+            .collect();
+
+        let n = hits.len() as f64;
+        (!hits.is_empty()).then(|| {
+            hits.iter()
+                .fold((0., 0.), |(z, r), hit| (z + hit.0 / n, r + hit.1 / n))
+        })
+    }
+
+    /// Depth camera z and reflectance where a ray first hits the board or
+    /// the wall behind it
+    fn trace(&self, origin: [f64; 3], direction: [f64; 3]) -> (f64, f64) {
+        let wall = || {
+            let s = (WALL_DEPTH as f64 - origin[2]) / direction[2];
+            (origin[2] + s * direction[2], 0.5)
+        };
+
+        let r = &self.rotation;
+        let normal = [r[0][2], r[1][2], r[2][2]];
+        let dot = |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+
+        let facing = dot(normal, direction);
+        if facing.abs() < 1e-9 {
+            return wall();
+        }
+
+        let offset = [0, 1, 2].map(|i| self.translation[i] - origin[i]);
+        let s = dot(normal, offset) / facing;
+        if s <= 0. {
+            return wall();
+        }
+
+        // Hit point on the board plane, in millimetres from the first corner
+        let hit = [0, 1, 2].map(|i| origin[i] + s * direction[i] - self.translation[i]);
+        let board_x = r[0][0] * hit[0] + r[1][0] * hit[1] + r[2][0] * hit[2];
+        let board_y = r[0][1] * hit[0] + r[1][1] * hit[1] + r[2][1] * hit[2];
+
+        // Squares run from one square before the first inner corner to one
+        // after the last, inside a white margin of half a square
+        let square = self.square_mm;
+        let margin = 1.5 * square;
+        let inside = |value: f64, corners: usize| {
+            (-margin..(corners as f64 - 1.) * square + margin).contains(&value)
+        };
+        if !inside(board_x, self.columns) || !inside(board_y, self.rows) {
+            return wall();
+        }
+
+        let square_x = (board_x / square).floor() as i64 + 1;
+        let square_y = (board_y / square).floor() as i64 + 1;
+        let on_squares = (0..=self.columns as i64).contains(&square_x)
+            && (0..=self.rows as i64).contains(&square_y);
+
+        // The square at the corner next to the first inner corner is dark
+        let dark = on_squares && (square_x + square_y) % 2 == 0;
+        (origin[2] + s * direction[2], if dark { 0.08 } else { 1. })
+    }
+}
+
+impl FrameSource for SyntheticSource {
+    fn apply_config(&mut self, config: &FrameConfig) -> Result<(), Box<dyn std::error::Error>> {
+        self.config = *config;
+        Ok(())
+    }
+
+    fn fetch_frame(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        thread::sleep(FRAME_INTERVAL);
+
+        let elapsed = self.start.elapsed();
+        let frames = self.render(elapsed.as_secs_f32());
+
+        self.frame_id += 1;
+        encode_frame(
+            self.frame_id,
+            elapsed.as_millis() as u64,
+            &self.config,
+            &frames,
+        )
+    }
+
+    fn device_info(&mut self) -> Result<Option<DeviceInfo>, Box<dyn std::error::Error>> {
+        // Only the checkerboard is drawn through a lens
+        let lens = match self.scene {
+            SyntheticScene::Ball => None,
+            SyntheticScene::Checkerboard { .. } => Some(SYNTHETIC_DEPTH_INTRINSICS),
+        };
+
+        Ok(Some(DeviceInfo {
+            serial: SYNTHETIC_SERIAL.to_string(),
+            lens,
+        }))
+    }
+}
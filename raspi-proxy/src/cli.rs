@@ -0,0 +1,589 @@
+use std::path::PathBuf;
+
+use crate::{
+    calibrate::Board,
+    camera::{
+        Calibration, DeepMode, Discontinuity, FilterConfig, FrameConfig, FrameSource, IrMode,
+        MeshConfig, RecordingSource, ReplayPacing, ReplaySource, RgbMode, RgbRes, SipeedHttpSource,
+        StatusMode, SyntheticScene, SyntheticSource, TriggerMode, DEFAULT_HOST, DEFAULT_PORT,
+    },
+};
+
+const USAGE: &str = "Usage: raspi-proxy [OPTIONS]
+       raspi-proxy mock-camera [MOCK OPTIONS]
+       raspi-proxy calibrate-tof [CALIBRATE OPTIONS]
+       raspi-proxy calibrate-rgb [CALIBRATE OPTIONS]
+
+Source options:
+    --camera <HOST[:PORT]>         Camera address (default 192.168.233.1:80)
+    --synthetic                    Generate a test scene instead of using a camera
+    --replay <FILE>                Replay a recording file instead of using a camera
+    --replay-speed <X|max>         Replay X times faster than recorded (default 1)
+    --replay-step                  Replay one frame each time Enter is pressed
+    --record <FILE>                Save every raw frame to a recording file
+    --calibration <FILE>           Camera calibration to use (default
+                                   calibration/<SERIAL>.txt or calibration.txt
+                                   if either exists, else the camera's own)
+
+Camera options:
+    --trigger <stop|auto|single>   Capture trigger mode (default auto)
+    --deep-mode <16|8>             Depth image bit depth (default 16)
+    --deep-shift <0-11|255>        8-bit depth shift, 255 for firmware default
+    --ir-mode <16|8>               IR image bit depth (default 16)
+    --status-mode <16|8|2|1>       Status image bits per pixel (default 8)
+    --status-mask <N>              Status mask (default 7)
+    --rgb-mode <yuv|jpeg>          RGB encoding (default jpeg)
+    --rgb-res <640|800>            RGB width (default 640)
+    --exposure <N>                 Exposure time, 0 for auto (default 0)
+
+Filter options:
+    --min-ir <N>                   Drop pixels with a weaker IR return, in the
+                                   units of --ir-mode (default 16)
+    --confidence-ignores-status    Do not drop pixels flagged in the status image
+    --no-confidence                Turn the IR confidence threshold off
+    --temporal-alpha <0-1>         Weight of the newest frame in the temporal
+                                   average, 1 for none (default 0.5)
+    --temporal-reset <MM>          Depth change that restarts a pixel's
+                                   average (default 100)
+    --no-temporal                  Turn the temporal filter off
+    --median-radius <N>            Median window radius in pixels (default 1)
+    --no-median                    Turn the median filter off
+    --bilateral-radius <N>         Bilateral window radius in pixels (default 2)
+    --bilateral-sigma-space <PX>   Bilateral distance falloff (default 1.5)
+    --bilateral-sigma-depth <MM>   Bilateral depth difference falloff (default 30)
+    --no-bilateral                 Turn the bilateral filter off
+    --fill-radius <N>              Hole filling window radius (default 1)
+    --fill-min-neighbours <N>      Valid pixels needed to fill a hole (default 5)
+    --no-fill-holes                Turn hole filling off
+    --flying-angle <DEG>           Drop pixels on surfaces steeper than this to
+                                   both neighbours (default 80)
+    --flying-ratio <R>             Drop pixels whose depth jumps by more than R
+                                   times to both neighbours instead
+    --no-flying-pixels             Keep flying pixels
+
+Mesh options:
+    --mesh-max-edge <M>            Longest triangle edge in metres (default 0.05)
+    --mesh-max-jump <R>            Largest depth jump along a triangle edge, as a
+                                   fraction of the nearer depth (default 0.05)";
+
+const MOCK_USAGE: &str = "Usage: raspi-proxy mock-camera [MOCK OPTIONS]
+
+Serves /set_cfg, /getdeep and /getinfo like the camera, for running the proxy without one.
+
+Mock options:
+    --listen <ADDR>                Address to serve on (default 127.0.0.1:8080)
+    --replay <FILE>                Serve a recording file instead of a test scene
+    --replay-speed <X|max>         Replay X times faster than recorded (default 1)
+    --delay-ms <N>                 Delay every /getdeep response by N milliseconds
+    --http-error-every <N>         Answer every Nth request with HTTP 500
+    --size-mismatch-every <N>      Corrupt the deep data size of every Nth frame
+    --truncate-every <N>           Cut every Nth frame short";
+
+const CALIBRATE_USAGE: &str = "Usage: raspi-proxy calibrate-tof [CALIBRATE OPTIONS]
+       raspi-proxy calibrate-rgb [CALIBRATE OPTIONS]
+
+Calibrates the cameras from views of a checkerboard and saves the result to
+the calibration file. calibrate-tof finds the depth camera's lens from the IR
+image, calibrate-rgb the RGB camera's lens and its pose relative to the depth
+camera, so run it after calibrate-tof. Hold the board at different distances
+and angles until enough views are collected.
+
+Calibrate options:
+    --camera <HOST[:PORT]>         Camera address (default 192.168.233.1:80)
+    --synthetic                    Use a generated checkerboard instead
+    --replay <FILE>                Take frames from a recording file instead
+    --board <COLUMNSxROWS>         Inner corners of the board (default 7x6)
+    --square <MM>                  Side of a board square (default 35)
+    --views <N>                    Views of the board to collect (default 15)
+    --force                        Save a depth calibration even if its lens
+                                   leaves many more pixels without rays than
+                                   the default one
+    --output <FILE>                Calibration file to update (default
+                                   calibration/<SERIAL>.txt, or calibration.txt
+                                   for cameras without a serial number)";
+
+/// Subcommand picked by the first argument
+pub enum Command {
+    /// Serve point clouds to headset clients
+    Serve(Args),
+    /// Stand in for the camera's HTTP interface
+    MockCamera(MockCameraArgs),
+    /// Calibrate the depth camera from IR images
+    CalibrateTof(CalibrateArgs),
+    /// Calibrate the RGB camera against the depth camera
+    CalibrateRgb(CalibrateArgs),
+}
+
+impl Command {
+    pub fn parse() -> Result<Self, Box<dyn std::error::Error>> {
+        let mut args = std::env::args().skip(1).peekable();
+
+        match args.peek().map(String::as_str) {
+            Some("mock-camera") => {
+                args.next();
+                Ok(Command::MockCamera(MockCameraArgs::parse_from(args)?))
+            }
+            Some("calibrate-tof") => {
+                args.next();
+                Ok(Command::CalibrateTof(CalibrateArgs::parse_from(args)?))
+            }
+            Some("calibrate-rgb") => {
+                args.next();
+                Ok(Command::CalibrateRgb(CalibrateArgs::parse_from(args)?))
+            }
+            _ => Ok(Command::Serve(Args::parse_from(args)?)),
+        }
+    }
+}
+
+/// Where frames come from
+pub enum Source {
+    Sipeed { host: String, port: u16 },
+    Synthetic(SyntheticScene),
+    Replay { path: PathBuf, pacing: ReplayPacing },
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Source::Sipeed {
+            host: DEFAULT_HOST.to_string(),
+            port: DEFAULT_PORT,
+        }
+    }
+}
+
+impl Source {
+    pub fn open(&self) -> Result<Box<dyn FrameSource>, Box<dyn std::error::Error>> {
+        Ok(match self {
+            Source::Sipeed { host, port } => Box::new(SipeedHttpSource::new(host, *port)),
+            Source::Synthetic(scene) => Box::new(SyntheticSource::new(*scene)),
+            Source::Replay { path, pacing } => Box::new(ReplaySource::open(path, *pacing)?),
+        })
+    }
+}
+
+/// Command line options
+pub struct Args {
+    pub source: Source,
+    pub record: Option<PathBuf>,
+    /// Calibration file, None to look one up for each camera
+    pub calibration: Option<PathBuf>,
+    pub frame_config: FrameConfig,
+    pub filter_config: FilterConfig,
+    pub mesh_config: MeshConfig,
+}
+
+impl Args {
+    /// Opens the frame source, recording it if asked to
+    pub fn open_source(&self) -> Result<Box<dyn FrameSource>, Box<dyn std::error::Error>> {
+        let source = self.source.open()?;
+
+        match self.record {
+            Some(ref path) => Ok(Box::new(RecordingSource::new(source, path)?)),
+            None => Ok(source),
+        }
+    }
+
+    /// Reads the calibration file given, None when there is none
+    pub fn load_calibration(&self) -> Result<Option<Calibration>, Box<dyn std::error::Error>> {
+        self.calibration.as_deref().map(Calibration::load).transpose()
+    }
+
+    fn parse_from(
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut source = Source::default();
+        let mut pacing = ReplayPacing::Timed(1.);
+        let mut record = None;
+        let mut calibration = None;
+        let mut config = FrameConfig::builder();
+        let mut filters = FilterConfig::default();
+        let mut mesh = MeshConfig::default();
+        let mut confidence = filters.confidence.unwrap_or_default();
+        let mut temporal = filters.temporal.unwrap_or_default();
+        let mut median = filters.median.unwrap_or_default();
+        let mut bilateral = filters.bilateral.unwrap_or_default();
+        let mut fill_holes = filters.fill_holes.unwrap_or_default();
+        let mut flying_pixels = filters.flying_pixels.unwrap_or_default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                "--synthetic" => {
+                    source = Source::Synthetic(SyntheticScene::Ball);
+                    continue;
+                }
+                "--replay-step" => {
+                    pacing = ReplayPacing::Step;
+                    continue;
+                }
+                "--confidence-ignores-status" => {
+                    confidence.use_status = false;
+                    continue;
+                }
+                "--no-confidence" => {
+                    filters.confidence = None;
+                    continue;
+                }
+                "--no-temporal" => {
+                    filters.temporal = None;
+                    continue;
+                }
+                "--no-median" => {
+                    filters.median = None;
+                    continue;
+                }
+                "--no-bilateral" => {
+                    filters.bilateral = None;
+                    continue;
+                }
+                "--no-fill-holes" => {
+                    filters.fill_holes = None;
+                    continue;
+                }
+                "--no-flying-pixels" => {
+                    filters.flying_pixels = None;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}\n\n{}", arg, USAGE))?;
+
+            config = match arg.as_str() {
+                "--camera" => {
+                    source = parse_camera(value)?;
+                    continue;
+                }
+                "--replay" => {
+                    source = Source::Replay {
+                        path: PathBuf::from(value),
+                        pacing,
+                    };
+                    continue;
+                }
+                "--replay-speed" => {
+                    pacing = parse_speed(&arg, &value)?;
+                    continue;
+                }
+                "--record" => {
+                    record = Some(PathBuf::from(value));
+                    continue;
+                }
+                "--calibration" => {
+                    calibration = Some(PathBuf::from(value));
+                    continue;
+                }
+                "--min-ir" => {
+                    confidence.min_amplitude = value.parse()?;
+                    continue;
+                }
+                "--temporal-alpha" => {
+                    temporal.alpha = value.parse()?;
+                    continue;
+                }
+                "--temporal-reset" => {
+                    temporal.reset_mm = value.parse()?;
+                    continue;
+                }
+                "--median-radius" => {
+                    median.radius = value.parse()?;
+                    continue;
+                }
+                "--bilateral-radius" => {
+                    bilateral.radius = value.parse()?;
+                    continue;
+                }
+                "--bilateral-sigma-space" => {
+                    bilateral.sigma_space = value.parse()?;
+                    continue;
+                }
+                "--bilateral-sigma-depth" => {
+                    bilateral.sigma_depth_mm = value.parse()?;
+                    continue;
+                }
+                "--fill-radius" => {
+                    fill_holes.radius = value.parse()?;
+                    continue;
+                }
+                "--fill-min-neighbours" => {
+                    fill_holes.min_neighbours = value.parse()?;
+                    continue;
+                }
+                "--flying-angle" => {
+                    flying_pixels.threshold = Discontinuity::Angle(value.parse()?);
+                    continue;
+                }
+                "--flying-ratio" => {
+                    flying_pixels.threshold = Discontinuity::Ratio(value.parse()?);
+                    continue;
+                }
+                "--mesh-max-edge" => {
+                    mesh.max_edge = value.parse()?;
+                    continue;
+                }
+                "--mesh-max-jump" => {
+                    mesh.max_depth_jump = value.parse()?;
+                    continue;
+                }
+                "--trigger" => config.trigger_mode(match value.as_str() {
+                    "stop" => TriggerMode::Stop,
+                    "auto" => TriggerMode::Auto,
+                    "single" => TriggerMode::Single,
+                    _ => return Err(invalid(&arg, &value)),
+                }),
+                "--deep-mode" => config.deep_mode(match value.as_str() {
+                    "16" => DeepMode::Bits16,
+                    "8" => DeepMode::Bits8,
+                    _ => return Err(invalid(&arg, &value)),
+                }),
+                "--deep-shift" => config.deep_shift(value.parse()?),
+                "--ir-mode" => config.ir_mode(match value.as_str() {
+                    "16" => IrMode::Bits16,
+                    "8" => IrMode::Bits8,
+                    _ => return Err(invalid(&arg, &value)),
+                }),
+                "--status-mode" => config.status_mode(match value.as_str() {
+                    "16" => StatusMode::Bits16,
+                    "8" => StatusMode::Bits8,
+                    "2" => StatusMode::Bits2,
+                    "1" => StatusMode::Bits1,
+                    _ => return Err(invalid(&arg, &value)),
+                }),
+                "--status-mask" => config.status_mask(value.parse()?),
+                "--rgb-mode" => config.rgb_mode(match value.as_str() {
+                    "yuv" => RgbMode::Yuv,
+                    "jpeg" => RgbMode::Jpeg,
+                    _ => return Err(invalid(&arg, &value)),
+                }),
+                "--rgb-res" => config.rgb_res(match value.as_str() {
+                    "640" => RgbRes::Vga,
+                    "800" => RgbRes::Svga,
+                    _ => return Err(invalid(&arg, &value)),
+                }),
+                "--exposure" => config.expose_time(value.parse()?),
+                _ => return Err(format!("Unknown option {}\n\n{}", arg, USAGE).into()),
+            };
+        }
+
+        set_pacing(&mut source, pacing);
+
+        // Tuning options apply to filters that were not turned off
+        filters.confidence = filters.confidence.and(Some(confidence));
+        filters.temporal = filters.temporal.and(Some(temporal));
+        filters.median = filters.median.and(Some(median));
+        filters.bilateral = filters.bilateral.and(Some(bilateral));
+        filters.fill_holes = filters.fill_holes.and(Some(fill_holes));
+        filters.flying_pixels = filters.flying_pixels.and(Some(flying_pixels));
+        filters.validate()?;
+        mesh.validate()?;
+
+        Ok(Self {
+            source,
+            record,
+            calibration,
+            frame_config: config.build()?,
+            filter_config: filters,
+            mesh_config: mesh,
+        })
+    }
+}
+
+/// Faults the mock camera injects into its responses
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MockFaults {
+    pub delay_ms: u64,
+    pub http_error_every: Option<u64>,
+    pub size_mismatch_every: Option<u64>,
+    pub truncate_every: Option<u64>,
+}
+
+/// Options for the `mock-camera` subcommand
+pub struct MockCameraArgs {
+    pub listen: String,
+    pub source: Source,
+    pub faults: MockFaults,
+}
+
+impl MockCameraArgs {
+    fn parse_from(
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut mock = Self {
+            listen: "127.0.0.1:8080".to_string(),
+            source: Source::Synthetic(SyntheticScene::Ball),
+            faults: MockFaults::default(),
+        };
+        let mut pacing = ReplayPacing::Timed(1.);
+
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                println!("{}", MOCK_USAGE);
+                std::process::exit(0);
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}\n\n{}", arg, MOCK_USAGE))?;
+
+            match arg.as_str() {
+                "--listen" => mock.listen = value,
+                "--replay" => {
+                    mock.source = Source::Replay {
+                        path: PathBuf::from(value),
+                        pacing,
+                    }
+                }
+                "--replay-speed" => pacing = parse_speed(&arg, &value)?,
+                "--delay-ms" => mock.faults.delay_ms = value.parse()?,
+                "--http-error-every" => mock.faults.http_error_every = Some(every(&arg, &value)?),
+                "--size-mismatch-every" => {
+                    mock.faults.size_mismatch_every = Some(every(&arg, &value)?)
+                }
+                "--truncate-every" => mock.faults.truncate_every = Some(every(&arg, &value)?),
+                _ => return Err(format!("Unknown option {}\n\n{}", arg, MOCK_USAGE).into()),
+            }
+        }
+
+        set_pacing(&mut mock.source, pacing);
+
+        Ok(mock)
+    }
+}
+
+/// Options for the `calibrate-tof` and `calibrate-rgb` subcommands
+pub struct CalibrateArgs {
+    pub source: Source,
+    pub board: Board,
+    /// Views of the board to collect
+    pub views: usize,
+    /// Calibration file to update, None to pick one by serial number
+    pub output: Option<PathBuf>,
+    /// Save a depth calibration that leaves many pixels without rays
+    pub force: bool,
+}
+
+impl CalibrateArgs {
+    fn parse_from(
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut calibrate = Self {
+            source: Source::default(),
+            board: Board::default(),
+            views: 15,
+            output: None,
+            force: false,
+        };
+        let mut synthetic = false;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => {
+                    println!("{}", CALIBRATE_USAGE);
+                    std::process::exit(0);
+                }
+                "--synthetic" => {
+                    synthetic = true;
+                    continue;
+                }
+                "--force" => {
+                    calibrate.force = true;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}\n\n{}", arg, CALIBRATE_USAGE))?;
+
+            match arg.as_str() {
+                "--camera" => calibrate.source = parse_camera(value)?,
+                "--replay" => {
+                    // Calibration only wants distinct views, no need to wait
+                    calibrate.source = Source::Replay {
+                        path: PathBuf::from(value),
+                        pacing: ReplayPacing::Unthrottled,
+                    }
+                }
+                "--board" => {
+                    let (columns, rows) =
+                        value.split_once('x').ok_or_else(|| invalid(&arg, &value))?;
+                    calibrate.board.columns = columns.parse()?;
+                    calibrate.board.rows = rows.parse()?;
+                }
+                "--square" => calibrate.board.square_mm = value.parse()?,
+                "--views" => {
+                    calibrate.views = match value.parse() {
+                        Ok(views) if views >= 3 => views,
+                        _ => return Err(invalid(&arg, &value)),
+                    }
+                }
+                "--output" => calibrate.output = Some(PathBuf::from(value)),
+                _ => return Err(format!("Unknown option {}\n\n{}", arg, CALIBRATE_USAGE).into()),
+            }
+        }
+
+        calibrate.board.validate()?;
+
+        // The board is only known once every option is read
+        if synthetic {
+            calibrate.source = Source::Synthetic(SyntheticScene::Checkerboard {
+                columns: calibrate.board.columns,
+                rows: calibrate.board.rows,
+                square_mm: calibrate.board.square_mm,
+            });
+        }
+
+        Ok(calibrate)
+    }
+}
+
+/// Parses a `--camera` address
+fn parse_camera(value: String) -> Result<Source, Box<dyn std::error::Error>> {
+    Ok(match value.split_once(':') {
+        Some((host, port)) => Source::Sipeed {
+            host: host.to_string(),
+            port: port.parse()?,
+        },
+        None => Source::Sipeed {
+            host: value,
+            port: DEFAULT_PORT,
+        },
+    })
+}
+
+/// Applies pacing options regardless of whether they came before `--replay`
+fn set_pacing(source: &mut Source, pacing: ReplayPacing) {
+    if let Source::Replay { pacing: ref mut replay_pacing, .. } = *source {
+        *replay_pacing = pacing;
+    }
+}
+
+fn parse_speed(arg: &str, value: &str) -> Result<ReplayPacing, Box<dyn std::error::Error>> {
+    if value == "max" {
+        return Ok(ReplayPacing::Unthrottled);
+    }
+
+    match value.parse::<f64>() {
+        Ok(speed) if speed > 0. && speed.is_finite() => Ok(ReplayPacing::Timed(speed)),
+        _ => Err(invalid(arg, value)),
+    }
+}
+
+/// Parses a non-zero "every Nth" count
+fn every(arg: &str, value: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    match value.parse() {
+        Ok(0) | Err(_) => Err(invalid(arg, value)),
+        Ok(n) => Ok(n),
+    }
+}
+
+fn invalid(arg: &str, value: &str) -> Box<dyn std::error::Error> {
+    format!("Invalid value for {}: {}", arg, value).into()
+}
@@ -1,99 +1,332 @@
 #[macro_use]
 extern crate log;
+mod calibrate;
 mod camera;
+mod cli;
+mod mock_camera;
 
-use std::{io::{Read, Write}, net::{TcpListener, TcpStream}};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
 
-use camera::SipeedCamera;
+use camera::{triangulate, FrameConfig, MeshConfig, Point, SipeedCamera};
+use cli::Command;
+use signal_hook::consts::{SIGINT, SIGTERM};
 
-const SOCKET: &'static str = "0.0.0.0:1234";
+const SOCKET: &str = "0.0.0.0:1234";
+
+/// How often blocked accepts and reads check for a shutdown signal
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
 
 enum DataBlocks {
     Error = 0,
     PointCloudData = 1,
-    ReadyData = 2
+    ReadyData = 2,
+    ConfigData = 3,
+    Shutdown = 4,
+    /// Asks for the next cloud as `OrganizedPointCloudData`
+    OrganizedReadyData = 5,
+    /// Every depth pixel row by row, NaN positions where there is no point
+    OrganizedPointCloudData = 6,
+    /// Asks for the next cloud as `MeshData`
+    MeshReadyData = 7,
+    /// Vertices then triangles as three `u32` vertex indices each
+    MeshData = 8,
 }
 
 pub fn main() {
     pretty_env_logger::init();
 
-    let mut camera = SipeedCamera::default();
+    let args = match Command::parse() {
+        Ok(Command::Serve(args)) => args,
+        Ok(Command::MockCamera(args)) => {
+            if let Err(e) = mock_camera::run(args) {
+                error!("Mock camera failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(Command::CalibrateTof(args)) => {
+            if let Err(e) = calibrate::run_tof(args) {
+                error!("Calibration failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(Command::CalibrateRgb(args)) => {
+            if let Err(e) = calibrate::run_rgb(args) {
+                error!("Calibration failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
 
-    info!("Connection established with camera");
+    let calibration = match args.load_calibration() {
+        Ok(calibration) => calibration,
+        Err(e) => {
+            eprintln!("Failed to load calibration: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // loop {
-    //     let points = camera.get_points();
-    //     info!("{:?}", points);
-    // }
+    let source = match args.open_source() {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Failed to open frame source: {}", e);
+            std::process::exit(1);
+        }
+    };
 
+    // Set by SIGINT/SIGTERM, everything below polls it. A second signal
+    // exits at once in case shutting down hangs.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&shutdown))
+            .expect("Failed to register signal handler");
+        signal_hook::flag::register(signal, Arc::clone(&shutdown))
+            .expect("Failed to register signal handler");
+    }
+
+    let mut camera = SipeedCamera::new(source);
+    camera.set_config(args.frame_config);
+    camera.set_filter_config(args.filter_config);
+    if let Some(calibration) = calibration {
+        camera.set_calibration(calibration);
+    }
+
+    info!("Camera config: {:?}", camera.config());
+    info!("Filter config: {:?}", camera.filter_config());
+    info!("Mesh config: {:?}", args.mesh_config);
+    match args.calibration {
+        Some(_) => info!("Calibration: {:?}", camera.calibration()),
+        None => info!("Calibration: looked up for each camera"),
+    }
 
     let mut listener = TcpListener::bind(SOCKET).expect("Failed to bind");
+    listener
+        .set_nonblocking(true)
+        .expect("Failed to make listener non-blocking");
 
     info!("Server listening on {}", SOCKET);
 
-    loop {
-        match run_server(&mut camera, &mut listener) {
-            Err(e) => {
-                error!("{}", e);
-            }
-            _ => {}
+    while !shutdown.load(Ordering::Relaxed) {
+        if let Err(e) = run_server(&mut camera, &mut listener, &args.mesh_config, &shutdown) {
+            error!("{}", e);
         }
     }
 
-    // println!("Test!");
+    info!("Shutting down");
+
+    // Stops accepting before the camera threads are joined
+    drop(listener);
+    drop(camera);
 }
 
-fn run_server(camera: &mut SipeedCamera, listener: &mut TcpListener) -> Result<(), std::io::Error> {
-    for stream in listener.incoming() {
-        let mut stream = stream?;
+fn run_server(
+    camera: &mut SipeedCamera,
+    listener: &mut TcpListener,
+    mesh_config: &MeshConfig,
+    shutdown: &AtomicBool,
+) -> Result<(), std::io::Error> {
+    while !shutdown.load(Ordering::Relaxed) {
+        let mut stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
 
-        info!("New connection: {}", stream.peer_addr().unwrap());
-        run_stream(camera, &mut stream)?;
+        // Accepted sockets inherit non-blocking mode, reads time out instead
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
 
+        info!("New connection: {}", stream.peer_addr()?);
+        run_stream(camera, &mut stream, mesh_config, shutdown)?;
     }
 
     Ok(())
 }
 
-fn run_stream(camera: &mut SipeedCamera,stream: &mut TcpStream) ->  Result<(), std::io::Error> {
+fn run_stream(
+    camera: &mut SipeedCamera,
+    stream: &mut TcpStream,
+    mesh_config: &MeshConfig,
+    shutdown: &AtomicBool,
+) -> Result<(), std::io::Error> {
     loop {
+        if shutdown.load(Ordering::Relaxed) {
+            info!("Telling {} the server is going down", stream.peer_addr()?);
+            stream.write_all(&(DataBlocks::Shutdown as i32).to_le_bytes())?;
+            stream.flush()?;
+            stream.shutdown(Shutdown::Both)?;
+            return Ok(());
+        }
+
         let mut recv_buf = [0u8; 1];
-        stream.read_exact(&mut recv_buf)?;
-        assert!(recv_buf[0] == DataBlocks::ReadyData as u8);
+        match stream.read(&mut recv_buf) {
+            Ok(0) => {
+                info!("Client {} disconnected", stream.peer_addr()?);
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+
+        // Clients may swap the camera config between frames
+        if recv_buf[0] == DataBlocks::ConfigData as u8 {
+            let mut config_buf = [0u8; 12];
+            if !read_full(stream, &mut config_buf, shutdown)? {
+                continue;
+            }
+
+            // Unlike the camera's echo, a client's config must be valid
+            let config = FrameConfig::decode(&config_buf)
+                .and_then(|config| config.validate().map(|()| config));
+            match config {
+                Ok(config) => {
+                    camera.set_config(config);
+                    info!("Camera config set to {:?}", camera.config());
+                }
+                Err(e) => warn!("Rejected camera config: {}", e),
+            }
+            continue;
+        }
 
+        // Answer in the form the client asked for
+        let reply = match recv_buf[0] {
+            r if r == DataBlocks::ReadyData as u8 => DataBlocks::PointCloudData,
+            r if r == DataBlocks::OrganizedReadyData as u8 => DataBlocks::OrganizedPointCloudData,
+            r if r == DataBlocks::MeshReadyData as u8 => DataBlocks::MeshData,
+            r => {
+                warn!("Unknown request {} from {}", r, stream.peer_addr()?);
+                continue;
+            }
+        };
 
         let mut bytes = Vec::new();
 
-        let points = camera.get_points();
+        let cloud = camera.get_points();
+        let mut dropped_pixels = (0, 0);
 
-        if let Some(points) = points {
+        if let Some(cloud) = cloud {
+            dropped_pixels = (cloud.low_confidence, cloud.flying_pixels);
 
+            match reply {
+                DataBlocks::OrganizedPointCloudData => {
+                    let grid = cloud.organized();
+                    let (height, width) = grid.dim();
 
-            // bytes.append(&mut ("A").as_bytes().to_vec());
-            // bytes.append(&mut "B".as_bytes().to_vec());
+                    bytes.append(
+                        &mut (DataBlocks::OrganizedPointCloudData as i32)
+                            .to_le_bytes()
+                            .to_vec(),
+                    );
+                    bytes.append(&mut cloud.frame_id.to_le_bytes().to_vec());
+                    bytes.append(&mut cloud.stamp_msec.to_le_bytes().to_vec());
+                    bytes.append(&mut (width as i32).to_le_bytes().to_vec());
+                    bytes.append(&mut (height as i32).to_le_bytes().to_vec());
 
-            bytes.append(&mut (DataBlocks::PointCloudData as i32).to_le_bytes().to_vec());
-            bytes.append(&mut (points.len() as i32).to_le_bytes().to_vec());
-            // bytes.append(&mut points.len().to_le_bytes().to_vec());
+                    for point in grid.iter() {
+                        write_point(&mut bytes, point);
+                    }
+                }
+                DataBlocks::MeshData => {
+                    let mesh = triangulate(&cloud, mesh_config);
 
-            for point in points {
-                bytes.append(&mut point.0.to_le_bytes().to_vec());
-                bytes.append(&mut point.1.to_le_bytes().to_vec());
-                bytes.append(&mut point.2.to_le_bytes().to_vec());
-                bytes.append(&mut point.3.to_le_bytes().to_vec());
-                bytes.append(&mut point.4.to_le_bytes().to_vec());
-                bytes.append(&mut point.5.to_le_bytes().to_vec());
-            }
+                    bytes.append(&mut (DataBlocks::MeshData as i32).to_le_bytes().to_vec());
+                    bytes.append(&mut mesh.frame_id.to_le_bytes().to_vec());
+                    bytes.append(&mut mesh.stamp_msec.to_le_bytes().to_vec());
+                    bytes.append(&mut (mesh.vertices.len() as i32).to_le_bytes().to_vec());
+                    bytes.append(&mut (mesh.indices.len() as i32).to_le_bytes().to_vec());
+
+                    for vertex in &mesh.vertices {
+                        write_point(&mut bytes, vertex);
+                    }
+                    for index in mesh.indices {
+                        bytes.extend_from_slice(&index.to_le_bytes());
+                    }
+                }
+                _ => {
+                    bytes.append(&mut (DataBlocks::PointCloudData as i32).to_le_bytes().to_vec());
+                    bytes.append(&mut cloud.frame_id.to_le_bytes().to_vec());
+                    bytes.append(&mut cloud.stamp_msec.to_le_bytes().to_vec());
+                    bytes.append(&mut (cloud.points.len() as i32).to_le_bytes().to_vec());
 
+                    for point in &cloud.points {
+                        write_point(&mut bytes, point);
+                    }
+                }
+            }
         } else {
+            // Tell the client why there are no points
             bytes.append(&mut (DataBlocks::Error as i32).to_le_bytes().to_vec());
+            bytes.append(&mut (camera.connection_state() as i32).to_le_bytes().to_vec());
         }
 
-
-
         stream.write_all(&bytes)?;
         stream.flush()?;
 
-        info!("Sent {} bytes", bytes.len());
+        info!(
+            "Sent {} bytes, {} low confidence and {} flying pixels removed, {} frames dropped so far",
+            bytes.len(),
+            dropped_pixels.0,
+            dropped_pixels.1,
+            camera.dropped_frames()
+        );
     }
-}
\ No newline at end of file
+}
+
+/// Reads exactly `buf.len()` bytes, waiting out read timeouts
+///
+/// Returns false if the server started shutting down first.
+fn read_full(
+    stream: &mut TcpStream,
+    buf: &mut [u8],
+    shutdown: &AtomicBool,
+) -> Result<bool, std::io::Error> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        if shutdown.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+
+        match stream.read(&mut buf[filled..]) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(read) => filled += read,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Position in metres then colour, in the frame documented on `Point`
+fn write_point(bytes: &mut Vec<u8>, point: &Point) {
+    bytes.extend_from_slice(&point.x.to_le_bytes());
+    bytes.extend_from_slice(&point.y.to_le_bytes());
+    bytes.extend_from_slice(&point.z.to_le_bytes());
+    bytes.extend_from_slice(&[point.r, point.g, point.b]);
+}
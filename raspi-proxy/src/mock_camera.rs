@@ -0,0 +1,290 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    camera::{FrameConfig, FrameSource},
+    cli::{MockCameraArgs, MockFaults},
+};
+
+/// Offset of the deep data size field in a `/getdeep` body
+const DEEP_SIZE_OFFSET: usize = 28;
+
+/// State shared by every connection to the mock camera
+struct MockCamera {
+    source: Mutex<Box<dyn FrameSource>>,
+    faults: MockFaults,
+    requests: AtomicU64,
+    frames: AtomicU64,
+}
+
+/// Serves `/set_cfg`, `/getdeep` and `/getinfo` like the camera's HTTP interface
+pub fn run(args: MockCameraArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let source = args.source.open()?;
+
+    let listener = TcpListener::bind(&args.listen)?;
+    info!("Mock camera listening on {} with {:?}", args.listen, args.faults);
+
+    serve(listener, source, args.faults)
+}
+
+/// Answers connections on `listener` with frames from `source`
+fn serve(
+    listener: TcpListener,
+    source: Box<dyn FrameSource>,
+    faults: MockFaults,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let camera = Arc::new(MockCamera {
+        source: Mutex::new(source),
+        faults,
+        requests: AtomicU64::new(0),
+        frames: AtomicU64::new(0),
+    });
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let camera = Arc::clone(&camera);
+
+        thread::spawn(move || {
+            let peer = stream.peer_addr().ok();
+            debug!("Mock camera connection from {:?}", peer);
+
+            if let Err(e) = serve_connection(&camera, stream) {
+                debug!("Mock camera connection {:?} closed: {}", peer, e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Handles keep-alive requests until the client hangs up
+fn serve_connection(camera: &MockCamera, stream: TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line)? == 0 {
+            return Ok(());
+        }
+
+        // Only Content-Length matters, the camera never sees chunked bodies
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = header.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                content_length = value.trim().parse()?;
+            }
+        }
+
+        let mut body = vec![0u8; content_length];
+        reader.read_exact(&mut body)?;
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or("");
+        let path = parts.next().unwrap_or("");
+        trace!("Mock camera request {} {}", method, path);
+
+        let (status, response) = camera.handle(method, path, &body);
+        write_response(&mut writer, status, &response)?;
+    }
+}
+
+impl MockCamera {
+    fn handle(&self, method: &str, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+        let request = self.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        if is_nth(self.faults.http_error_every, request) {
+            debug!("Injecting HTTP error into request {}", request);
+            return (500, b"injected error".to_vec());
+        }
+
+        match (method, path) {
+            ("POST", "/set_cfg") => match FrameConfig::decode(body) {
+                Ok(config) => match self.source.lock().unwrap().apply_config(&config) {
+                    Ok(()) => {
+                        info!("Mock camera config set to {:?}", config);
+                        (200, Vec::new())
+                    }
+                    Err(e) => (500, e.to_string().into_bytes()),
+                },
+                Err(e) => (400, e.to_string().into_bytes()),
+            },
+            ("GET", "/getdeep") => {
+                if self.faults.delay_ms > 0 {
+                    thread::sleep(Duration::from_millis(self.faults.delay_ms));
+                }
+
+                match self.source.lock().unwrap().fetch_frame() {
+                    Ok(frame) => (200, self.corrupt(frame)),
+                    Err(e) => (500, e.to_string().into_bytes()),
+                }
+            }
+            ("GET", "/getinfo") => match self.source.lock().unwrap().device_info() {
+                Ok(Some(info)) => (200, info.to_json().into_bytes()),
+                Ok(None) => (404, Vec::new()),
+                Err(e) => (500, e.to_string().into_bytes()),
+            },
+            _ => (404, Vec::new()),
+        }
+    }
+
+    /// Applies the frame faults that are due
+    fn corrupt(&self, mut frame: Vec<u8>) -> Vec<u8> {
+        let index = self.frames.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if is_nth(self.faults.size_mismatch_every, index) && frame.len() >= DEEP_SIZE_OFFSET + 4 {
+            debug!("Injecting size mismatch into frame {}", index);
+            let field = &mut frame[DEEP_SIZE_OFFSET..DEEP_SIZE_OFFSET + 4];
+            let size = i32::from_le_bytes(field.try_into().unwrap());
+            field.copy_from_slice(&(size + 1024).to_le_bytes());
+        }
+
+        if is_nth(self.faults.truncate_every, index) {
+            debug!("Truncating frame {}", index);
+            frame.truncate(frame.len() / 2);
+        }
+
+        frame
+    }
+}
+
+fn is_nth(every: Option<u64>, count: u64) -> bool {
+    every.is_some_and(|every| count.is_multiple_of(every))
+}
+
+fn write_response(stream: &mut TcpStream, status: u16, body: &[u8]) -> Result<(), std::io::Error> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        _ => "Internal Server Error",
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+        status,
+        reason,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{decode_frame, SipeedHttpSource, SyntheticScene, SyntheticSource};
+
+    /// Starts a mock camera on a free port and connects to it
+    fn connect(faults: MockFaults) -> SipeedHttpSource {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let source = Box::new(SyntheticSource::new(SyntheticScene::Ball));
+
+        thread::spawn(move || serve(listener, source, faults).map_err(|e| e.to_string()));
+
+        SipeedHttpSource::new("127.0.0.1", port)
+    }
+
+    fn deep_size(frame: &[u8]) -> i32 {
+        i32::from_le_bytes(frame[DEEP_SIZE_OFFSET..DEEP_SIZE_OFFSET + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn healthy_frames_decode() {
+        let mut camera = connect(MockFaults::default());
+
+        let frame = camera.fetch_frame().unwrap();
+        let frames = decode_frame(&frame).unwrap();
+
+        assert_eq!(frames.resolution.shape(), (240, 320));
+        assert!(frames.depth.is_some());
+    }
+
+    #[test]
+    fn delay_times_out() {
+        let mut camera = connect(MockFaults {
+            delay_ms: 3000,
+            ..MockFaults::default()
+        });
+
+        let error = camera.fetch_frame().unwrap_err();
+
+        assert!(
+            matches!(error.downcast_ref(), Some(ureq::Error::Timeout(_))),
+            "expected a timeout, got {}",
+            error
+        );
+    }
+
+    #[test]
+    fn http_error_fails_fetch() {
+        let mut camera = connect(MockFaults {
+            http_error_every: Some(2),
+            ..MockFaults::default()
+        });
+
+        assert!(camera.fetch_frame().is_ok());
+        let error = camera.fetch_frame().unwrap_err();
+
+        assert!(
+            matches!(error.downcast_ref(), Some(ureq::Error::StatusCode(500))),
+            "expected HTTP 500, got {}",
+            error
+        );
+    }
+
+    #[test]
+    fn size_mismatch_falls_back_to_full_resolution() {
+        let mut camera = connect(MockFaults {
+            size_mismatch_every: Some(2),
+            ..MockFaults::default()
+        });
+
+        let clean = camera.fetch_frame().unwrap();
+        let mismatched = camera.fetch_frame().unwrap();
+        let frames = decode_frame(&mismatched).unwrap();
+
+        assert_eq!(deep_size(&mismatched), deep_size(&clean) + 1024);
+        assert_eq!(frames.resolution.shape(), (240, 320));
+        assert!(frames.depth.is_some());
+    }
+
+    #[test]
+    fn truncated_frame_fails_decode() {
+        let mut camera = connect(MockFaults {
+            truncate_every: Some(2),
+            ..MockFaults::default()
+        });
+
+        camera.fetch_frame().unwrap();
+        let truncated = camera.fetch_frame().unwrap();
+        let error = decode_frame(&truncated).map(|_| ()).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Frame truncated, {} of {} bytes of deep data",
+                truncated.len() - DEEP_SIZE_OFFSET - 8,
+                deep_size(&truncated)
+            )
+        );
+    }
+}